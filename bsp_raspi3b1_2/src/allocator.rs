@@ -0,0 +1,246 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::sync::SpinLock;
+
+// Every block (free or allocated) starts and ends on this boundary
+//    Large enough to hold the header of a free block, so any leftover of a split can be freed.
+const BLOCK_ALIGN: usize = 16;
+
+// Not the allocator of the host tools, which build this crate with the `builder` feature
+#[cfg_attr(not(feature = "builder"), global_allocator)]
+static ALLOCATOR: HeapAllocator = HeapAllocator::init();
+
+// The heap region, reserved by the linker script
+pub(crate) fn heap_region() -> (usize, usize) {
+    extern "C" {
+        static __heap_start: u8;
+        static __heap_end_exclusive: u8;
+    }
+    unsafe {
+        (
+            &__heap_start as *const u8 as usize,
+            &__heap_end_exclusive as *const u8 as usize,
+        )
+    }
+}
+
+// Hand the heap region to the global allocator, only once
+pub(crate) fn init_heap() {
+    let (start, end) = heap_region();
+    assert!(!ALLOCATOR.is_init(), "Heap already initialized");
+    unsafe { ALLOCATOR.add_region(start, end - start) };
+}
+
+/// Snapshot of the heap usage, in bytes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeapStats {
+    pub size: usize,
+    pub used: usize,
+    pub free: usize,
+    pub high_water_mark: usize,
+    pub allocations: usize,
+    pub failed_allocations: usize,
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "heap {} / {} bytes used ({} free, high-water mark {}), {} live allocations, {} failed",
+            self.used,
+            self.size,
+            self.free,
+            self.high_water_mark,
+            self.allocations,
+            self.failed_allocations
+        )
+    }
+}
+
+// Can be called at any time, even from the panic handler with the heap lock held
+pub fn heap_stats() -> HeapStats {
+    ALLOCATOR.stats()
+}
+
+// First-fit allocator over an address-ordered list of free blocks
+//    Freed blocks are merged with their neighbours, so the list stays as short as the
+//    fragmentation allows.
+pub(crate) struct HeapAllocator {
+    free_list: SpinLock<FreeList>,
+    // Kept outside of the lock, so they can be read while it is taken
+    size: AtomicUsize,
+    used: AtomicUsize,
+    high_water_mark: AtomicUsize,
+    allocations: AtomicUsize,
+    failed_allocations: AtomicUsize,
+}
+
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+struct FreeList {
+    head: *mut FreeBlock,
+}
+
+// Only ever accessed under the lock of the allocator
+unsafe impl Send for FreeList {}
+
+impl HeapAllocator {
+    pub(crate) const fn init() -> HeapAllocator {
+        HeapAllocator {
+            free_list: SpinLock::new_irqsafe(FreeList {
+                head: ptr::null_mut(),
+            }),
+            size: AtomicUsize::new(0),
+            used: AtomicUsize::new(0),
+            high_water_mark: AtomicUsize::new(0),
+            allocations: AtomicUsize::new(0),
+            failed_allocations: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn is_init(&self) -> bool {
+        self.size.load(Ordering::Relaxed) != 0
+    }
+
+    // Give a memory region to the allocator
+    //    Safety: the region must be unused, writable and never handed out by anything else
+    pub(crate) unsafe fn add_region(&self, start: usize, size: usize) {
+        let aligned_start = align_up(start, BLOCK_ALIGN);
+        let end = (start + size) & !(BLOCK_ALIGN - 1);
+        assert!(end > aligned_start, "Heap region too small");
+        let size = end - aligned_start;
+
+        self.free_list
+            .lock(|list| list.insert(aligned_start as *mut FreeBlock, size));
+        self.size.fetch_add(size, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stats(&self) -> HeapStats {
+        let size = self.size.load(Ordering::Relaxed);
+        let used = self.used.load(Ordering::Relaxed);
+        HeapStats {
+            size,
+            used,
+            free: size.saturating_sub(used),
+            high_water_mark: self.high_water_mark.load(Ordering::Relaxed),
+            allocations: self.allocations.load(Ordering::Relaxed),
+            failed_allocations: self.failed_allocations.load(Ordering::Relaxed),
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for HeapAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let (size, align) = block_layout(layout);
+        let ptr = self.free_list.lock(|list| list.allocate(size, align));
+        if ptr.is_null() {
+            // The caller reports it through `handle_alloc_error`, which ends in the panic handler
+            self.failed_allocations.fetch_add(1, Ordering::Relaxed);
+            return ptr;
+        }
+
+        let used = self.used.fetch_add(size, Ordering::Relaxed) + size;
+        self.high_water_mark.fetch_max(used, Ordering::Relaxed);
+        self.allocations.fetch_add(1, Ordering::Relaxed);
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = block_layout(layout);
+        self.free_list
+            .lock(|list| list.insert(ptr as *mut FreeBlock, size));
+        self.used.fetch_sub(size, Ordering::Relaxed);
+        self.allocations.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl FreeList {
+    // Carve a block out of the first free one large enough, null if there is none
+    unsafe fn allocate(&mut self, size: usize, align: usize) -> *mut u8 {
+        let mut prev: *mut FreeBlock = ptr::null_mut();
+        let mut current = self.head;
+
+        while !current.is_null() {
+            let block_start = current as usize;
+            let block_end = block_start + (*current).size;
+            let start = align_up(block_start, align);
+
+            if start + size <= block_end {
+                let next = (*current).next;
+                let front = start - block_start;
+                let back = block_end - (start + size);
+
+                // Everything is a multiple of BLOCK_ALIGN, so both leftovers are either empty or
+                //    large enough to become free blocks themselves.
+                let after = if back > 0 {
+                    let back_block = (start + size) as *mut FreeBlock;
+                    back_block.write(FreeBlock { size: back, next });
+                    back_block
+                } else {
+                    next
+                };
+
+                if front > 0 {
+                    (*current).size = front;
+                    (*current).next = after;
+                } else if prev.is_null() {
+                    self.head = after;
+                } else {
+                    (*prev).next = after;
+                }
+                return start as *mut u8;
+            }
+
+            prev = current;
+            current = (*current).next;
+        }
+        ptr::null_mut()
+    }
+
+    // Put a block back in the list, merging it with the adjacent free blocks
+    unsafe fn insert(&mut self, block: *mut FreeBlock, size: usize) {
+        let addr = block as usize;
+        let mut prev: *mut FreeBlock = ptr::null_mut();
+        let mut next = self.head;
+        while !next.is_null() && (next as usize) < addr {
+            prev = next;
+            next = (*next).next;
+        }
+
+        debug_assert!(
+            next.is_null() || addr + size <= next as usize,
+            "Freed block overlaps a free one"
+        );
+        block.write(FreeBlock { size, next });
+        if !next.is_null() && addr + size == next as usize {
+            (*block).size += (*next).size;
+            (*block).next = (*next).next;
+        }
+
+        if prev.is_null() {
+            self.head = block;
+        } else if prev as usize + (*prev).size == addr {
+            (*prev).size += (*block).size;
+            (*prev).next = (*block).next;
+        } else {
+            (*prev).next = block;
+        }
+    }
+}
+
+// Size and alignment of the block actually reserved for a layout
+fn block_layout(layout: Layout) -> (usize, usize) {
+    let size = align_up(layout.size().max(1), BLOCK_ALIGN);
+    let align = layout.align().max(BLOCK_ALIGN);
+    (size, align)
+}
+
+const fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
@@ -0,0 +1,257 @@
+use core::fmt;
+
+use crate::errors::Errcode;
+use crate::mailboxes::{PropertyRequest, RpiMailboxReply, RpiMailboxTag, MAILBOX};
+use crate::sync::Once;
+
+// The only board this BSP is written for: Raspberry Pi 3 Model B, revision 1.2
+const SUPPORTED_MODEL: BoardModel = BoardModel::Pi3B;
+const SUPPORTED_PROCESSOR: Processor = Processor::Bcm2837;
+const SUPPORTED_REVISION: u8 = 2;
+
+static BOARD_INFO: Once<BoardInfo> = Once::new();
+
+/// Everything the firmware tells about the board.
+#[derive(Debug, Clone, Copy)]
+pub struct BoardInfo {
+    pub firmware_version: u32,
+    pub model: u32,
+    pub revision: BoardRevision,
+    pub serial: u64,
+    pub mac_address: [u8; 6],
+    pub arm_memory: MemorySplit,
+    pub vc_memory: MemorySplit,
+}
+
+/// Part of the RAM given to the ARM or to the VideoCore.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemorySplit {
+    pub base: usize,
+    pub size: usize,
+}
+
+// Queried once from the firmware, then cached
+pub fn board_info() -> Result<&'static BoardInfo, Errcode> {
+    BOARD_INFO.try_call_once(query_board_info)
+}
+
+fn query_board_info() -> Result<BoardInfo, Errcode> {
+    let mut request = PropertyRequest::new();
+    request
+        .add(RpiMailboxTag::GetFirmwareVersion)?
+        .add(RpiMailboxTag::GetBoardModel)?
+        .add(RpiMailboxTag::GetBoardRevision)?
+        .add(RpiMailboxTag::GetBoardSerial)?
+        .add(RpiMailboxTag::GetBoardMacAddress)?
+        .add(RpiMailboxTag::GetArmMemory)?
+        .add(RpiMailboxTag::GetVcMemory)?;
+
+    let mut info = BoardInfo {
+        firmware_version: 0,
+        model: 0,
+        revision: BoardRevision(0),
+        serial: 0,
+        mac_address: [0; 6],
+        arm_memory: MemorySplit::default(),
+        vc_memory: MemorySplit::default(),
+    };
+    for reply in MAILBOX.send(&request)? {
+        match reply {
+            RpiMailboxReply::FirmwareVersion(version) => info.firmware_version = version,
+            RpiMailboxReply::BoardModel(model) => info.model = model,
+            RpiMailboxReply::BoardRevision(code) => info.revision = BoardRevision(code),
+            RpiMailboxReply::BoardSerial(serial) => info.serial = serial,
+            RpiMailboxReply::MacAddress(mac) => info.mac_address = mac,
+            RpiMailboxReply::ArmMemory { base, size } => {
+                info.arm_memory = MemorySplit::new(base, size)
+            }
+            RpiMailboxReply::VcMemory { base, size } => {
+                info.vc_memory = MemorySplit::new(base, size)
+            }
+            _ => unreachable!("Reply to a tag we did not send"),
+        }
+    }
+    Ok(info)
+}
+
+// Refuse to run on anything else than the board this crate is named after
+pub(crate) fn check_board() -> Result<(), Errcode> {
+    let revision = board_info()?.revision;
+    if revision.is_supported() {
+        Ok(())
+    } else {
+        Err(Errcode::UnsupportedBoard(revision.code()))
+    }
+}
+
+impl MemorySplit {
+    fn new(base: u32, size: u32) -> MemorySplit {
+        MemorySplit {
+            base: base as usize,
+            size: size as usize,
+        }
+    }
+}
+
+impl fmt::Display for BoardInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Board: {}", self.revision)?;
+        writeln!(f, "    Firmware version: {:#x}", self.firmware_version)?;
+        writeln!(f, "    Serial: {:016x}", self.serial)?;
+        let [a, b, c, d, e, g] = self.mac_address;
+        writeln!(
+            f,
+            "    MAC: {a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}"
+        )?;
+        writeln!(f, "    ARM memory: {}", self.arm_memory)?;
+        write!(f, "    VC memory: {}", self.vc_memory)
+    }
+}
+
+impl fmt::Display for MemorySplit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:#010x} - {:#010x} ({} MiB)",
+            self.base,
+            self.base + self.size,
+            self.size / (1024 * 1024)
+        )
+    }
+}
+
+// Revision code of the board, in the new-style encoding
+//    https://www.raspberrypi.com/documentation/computers/raspberry-pi.html#new-style-revision-codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoardRevision(u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoardModel {
+    PiA,
+    PiB,
+    PiAPlus,
+    PiBPlus,
+    Pi2B,
+    Alpha,
+    Cm1,
+    Pi3B,
+    PiZero,
+    Cm3,
+    PiZeroW,
+    Pi3BPlus,
+    Pi3APlus,
+    Cm3Plus,
+    Pi4B,
+    Unknown(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Processor {
+    Bcm2835,
+    Bcm2836,
+    Bcm2837,
+    Bcm2711,
+    Unknown(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Manufacturer {
+    SonyUk,
+    Egoman,
+    Embest,
+    SonyJapan,
+    Stadium,
+    Unknown(u8),
+}
+
+impl BoardRevision {
+    pub fn code(&self) -> u32 {
+        self.0
+    }
+
+    // Old-style codes (first boards) only give an index in a table, none of the fields below
+    pub fn is_new_style(&self) -> bool {
+        (self.0 & (1 << 23)) != 0
+    }
+
+    pub fn model(&self) -> BoardModel {
+        match self.field(4, 8) {
+            0x00 => BoardModel::PiA,
+            0x01 => BoardModel::PiB,
+            0x02 => BoardModel::PiAPlus,
+            0x03 => BoardModel::PiBPlus,
+            0x04 => BoardModel::Pi2B,
+            0x05 => BoardModel::Alpha,
+            0x06 => BoardModel::Cm1,
+            0x08 => BoardModel::Pi3B,
+            0x09 => BoardModel::PiZero,
+            0x0A => BoardModel::Cm3,
+            0x0C => BoardModel::PiZeroW,
+            0x0D => BoardModel::Pi3BPlus,
+            0x0E => BoardModel::Pi3APlus,
+            0x10 => BoardModel::Cm3Plus,
+            0x11 => BoardModel::Pi4B,
+            other => BoardModel::Unknown(other),
+        }
+    }
+
+    // Minor part of the "1.x" board revision
+    pub fn revision(&self) -> u8 {
+        self.field(0, 4)
+    }
+
+    pub fn processor(&self) -> Processor {
+        match self.field(12, 4) {
+            0 => Processor::Bcm2835,
+            1 => Processor::Bcm2836,
+            2 => Processor::Bcm2837,
+            3 => Processor::Bcm2711,
+            other => Processor::Unknown(other),
+        }
+    }
+
+    pub fn manufacturer(&self) -> Manufacturer {
+        match self.field(16, 4) {
+            0 => Manufacturer::SonyUk,
+            1 => Manufacturer::Egoman,
+            2 | 4 => Manufacturer::Embest,
+            3 => Manufacturer::SonyJapan,
+            5 => Manufacturer::Stadium,
+            other => Manufacturer::Unknown(other),
+        }
+    }
+
+    // Total RAM of the board in bytes, shared between the ARM and the VideoCore
+    pub fn memory_size(&self) -> usize {
+        (256 * 1024 * 1024) << self.field(20, 3)
+    }
+
+    pub fn is_supported(&self) -> bool {
+        self.is_new_style()
+            && self.model() == SUPPORTED_MODEL
+            && self.processor() == SUPPORTED_PROCESSOR
+            && self.revision() == SUPPORTED_REVISION
+    }
+
+    fn field(&self, offset: u32, bits: u32) -> u8 {
+        ((self.0 >> offset) & ((1 << bits) - 1)) as u8
+    }
+}
+
+impl fmt::Display for BoardRevision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.is_new_style() {
+            return write!(f, "unknown old-style board ({:#x})", self.0);
+        }
+        write!(
+            f,
+            "{:?} rev 1.{}, {:?}, {} MiB, made by {:?} ({:#x})",
+            self.model(),
+            self.revision(),
+            self.processor(),
+            self.memory_size() / (1024 * 1024),
+            self.manufacturer(),
+            self.0
+        )
+    }
+}
@@ -58,12 +58,46 @@ global_asm!(
         b.lo .L_copy_loop
 
     // Prepare the jump to Rust code.
-        // Set the stack pointer.
+        // Install the exception vector table.
+        ADR_ABS x0, __exception_vector_start
+        msr VBAR_EL1, x0
+
+        // The stack pointer used by the Rust code, once in EL1.
         ADR_ABS x0, __boot_core_stack_end_exclusive
+        ADR_ABS x1, _start_rust
+
+        mrs x2, CurrentEL
+        cmp x2, #(1 << 2)                 // Already in EL1 (chainloaded kernel)
+        b.eq .L_start_el1
+        cmp x2, #(2 << 2)                 // Firmware handed us EL2
+        b.ne .cpu_wait_loop
+
+        // Allow EL1 to access the physical timer and counter, no virtual offset.
+        mov x2, #3                        // CNTHCTL_EL2.EL1PCEN | CNTHCTL_EL2.EL1PCTEN
+        msr CNTHCTL_EL2, x2
+        msr CNTVOFF_EL2, xzr
+
+        // EL1 runs in AArch64.
+        mov x2, #(1 << 31)                // HCR_EL2.RW
+        msr HCR_EL2, x2
+
+        // Known state for EL1: MMU and caches off, little endian.
+        movz x2, #0x0800
+        movk x2, #0x30D0, lsl #16         // SCTLR_EL1 RES1 bits
+        msr SCTLR_EL1, x2
+
+        // Fake an exception return to EL1h, with all exceptions masked.
+        mov x2, #0x3C5                    // SPSR_EL2: D, A, I, F masked, EL1h
+        msr SPSR_EL2, x2
+        msr ELR_EL2, x1
+        msr SP_EL1, x0
+        eret
+
+    .L_start_el1:
+        msr DAIFSet, #0xF                 // Mask all exceptions until the BSP is initialized
         mov sp, x0
 
         // Jump to the relocated Rust code.
-        ADR_ABS x1, _start_rust
         br x1
 
     .cpu_wait_loop:
@@ -75,3 +109,120 @@ global_asm!(
     .global _start
 "
 );
+
+global_asm!(
+    r"
+    // Save the full GPR frame plus the exception registers on the stack, call the Rust handler
+    // with a pointer to this frame, then restore everything and return from the exception.
+    // Layout must match `exceptions::ExceptionContext`.
+    .macro CALL_WITH_CONTEXT handler
+    __vector_\handler:
+        sub sp,  sp,  #16 * 17
+
+        stp x0,  x1,  [sp, #16 * 0]
+        stp x2,  x3,  [sp, #16 * 1]
+        stp x4,  x5,  [sp, #16 * 2]
+        stp x6,  x7,  [sp, #16 * 3]
+        stp x8,  x9,  [sp, #16 * 4]
+        stp x10, x11, [sp, #16 * 5]
+        stp x12, x13, [sp, #16 * 6]
+        stp x14, x15, [sp, #16 * 7]
+        stp x16, x17, [sp, #16 * 8]
+        stp x18, x19, [sp, #16 * 9]
+        stp x20, x21, [sp, #16 * 10]
+        stp x22, x23, [sp, #16 * 11]
+        stp x24, x25, [sp, #16 * 12]
+        stp x26, x27, [sp, #16 * 13]
+        stp x28, x29, [sp, #16 * 14]
+
+        mrs x1,  ELR_EL1
+        mrs x2,  SPSR_EL1
+        mrs x3,  ESR_EL1
+
+        stp lr,  x1,  [sp, #16 * 15]
+        stp x2,  x3,  [sp, #16 * 16]
+
+        mov x0,  sp
+        bl \handler
+        b __exception_restore_context
+
+    .size __vector_\handler, . - __vector_\handler
+    .type __vector_\handler, function
+    .endm
+
+    // The vector table must be 2 KiB aligned, each of the 16 entries is 0x80 bytes long.
+    .section .text._exception_vectors
+    .balign 0x800
+
+    __exception_vector_start:
+
+    // Current exception level with SP_EL0.
+    .org 0x000
+        CALL_WITH_CONTEXT current_el0_synchronous
+    .org 0x080
+        CALL_WITH_CONTEXT current_el0_irq
+    .org 0x100
+        CALL_WITH_CONTEXT current_el0_fiq
+    .org 0x180
+        CALL_WITH_CONTEXT current_el0_serror
+
+    // Current exception level with SP_ELx, x > 0.
+    .org 0x200
+        CALL_WITH_CONTEXT current_elx_synchronous
+    .org 0x280
+        CALL_WITH_CONTEXT current_elx_irq
+    .org 0x300
+        CALL_WITH_CONTEXT current_elx_fiq
+    .org 0x380
+        CALL_WITH_CONTEXT current_elx_serror
+
+    // Lower exception level, AArch64
+    .org 0x400
+        CALL_WITH_CONTEXT lower_aarch64_synchronous
+    .org 0x480
+        CALL_WITH_CONTEXT lower_aarch64_irq
+    .org 0x500
+        CALL_WITH_CONTEXT lower_aarch64_fiq
+    .org 0x580
+        CALL_WITH_CONTEXT lower_aarch64_serror
+
+    // Lower exception level, AArch32
+    .org 0x600
+        CALL_WITH_CONTEXT lower_aarch32_synchronous
+    .org 0x680
+        CALL_WITH_CONTEXT lower_aarch32_irq
+    .org 0x700
+        CALL_WITH_CONTEXT lower_aarch32_fiq
+    .org 0x780
+        CALL_WITH_CONTEXT lower_aarch32_serror
+    .org 0x800
+
+    __exception_restore_context:
+        ldp x19, x20, [sp, #16 * 16]      // SPSR_EL1, ESR_EL1
+        ldp lr,  x20, [sp, #16 * 15]      // LR, ELR_EL1
+        msr SPSR_EL1, x19
+        msr ELR_EL1,  x20
+
+        ldp x0,  x1,  [sp, #16 * 0]
+        ldp x2,  x3,  [sp, #16 * 1]
+        ldp x4,  x5,  [sp, #16 * 2]
+        ldp x6,  x7,  [sp, #16 * 3]
+        ldp x8,  x9,  [sp, #16 * 4]
+        ldp x10, x11, [sp, #16 * 5]
+        ldp x12, x13, [sp, #16 * 6]
+        ldp x14, x15, [sp, #16 * 7]
+        ldp x16, x17, [sp, #16 * 8]
+        ldp x18, x19, [sp, #16 * 9]
+        ldp x20, x21, [sp, #16 * 10]
+        ldp x22, x23, [sp, #16 * 11]
+        ldp x24, x25, [sp, #16 * 12]
+        ldp x26, x27, [sp, #16 * 13]
+        ldp x28, x29, [sp, #16 * 14]
+
+        add sp,  sp,  #16 * 17
+        eret
+
+    .size __exception_restore_context, . - __exception_restore_context
+    .type __exception_restore_context, function
+"
+);
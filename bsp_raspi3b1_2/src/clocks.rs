@@ -0,0 +1,85 @@
+use crate::errors::Errcode;
+use crate::mailboxes::{RpiMailboxReply, RpiMailboxTag, TagClockId, MAILBOX};
+
+/// Clocks generated by the firmware that the kernel cares about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clock {
+    Arm,
+    Core,
+    Uart,
+    Emmc,
+    Pwm,
+}
+
+impl Clock {
+    fn tag_id(self) -> TagClockId {
+        match self {
+            Clock::Arm => TagClockId::Arm,
+            Clock::Core => TagClockId::Core,
+            Clock::Uart => TagClockId::Uart,
+            Clock::Emmc => TagClockId::Emmc,
+            Clock::Pwm => TagClockId::Pwm,
+        }
+    }
+}
+
+// All rates are in Hertz
+//    The firmware answers 0 for a clock the board does not have, reported as `ClockNotFound`.
+
+// Rate the clock is set to
+pub fn rate(clock: Clock) -> Result<u32, Errcode> {
+    query_rate(clock, RpiMailboxTag::GetClockRate(clock.tag_id()))
+}
+
+// Rate the clock actually runs at, as measured by the firmware
+//    Differs from `rate` while throttled, or when the PLL could not match the rate exactly.
+pub fn measured_rate(clock: Clock) -> Result<u32, Errcode> {
+    query_rate(clock, RpiMailboxTag::GetClockRateMeasured(clock.tag_id()))
+}
+
+pub fn max_rate(clock: Clock) -> Result<u32, Errcode> {
+    query_rate(clock, RpiMailboxTag::GetMaxClockRate(clock.tag_id()))
+}
+
+pub fn min_rate(clock: Clock) -> Result<u32, Errcode> {
+    query_rate(clock, RpiMailboxTag::GetMinClockRate(clock.tag_id()))
+}
+
+// Ask for a new rate, the firmware clamps it to the limits of the clock
+//    Return the rate actually set. The devices running from the clock are not told about the
+//    change, e.g. the UART has to be configured again to get its baud rate back.
+pub fn set_rate(clock: Clock, rate: u32) -> Result<u32, Errcode> {
+    query_rate(
+        clock,
+        RpiMailboxTag::SetClockRate {
+            clock_id: clock.tag_id(),
+            rate,
+            skip_set_turbo: false,
+        },
+    )
+}
+
+// Turbo runs the ARM, core and SDRAM clocks at their maximum rate
+pub fn turbo() -> Result<bool, Errcode> {
+    match MAILBOX.query(RpiMailboxTag::GetTurbo)? {
+        RpiMailboxReply::TurboState(on) => Ok(on),
+        _ => unreachable!("Reply to a tag we did not send"),
+    }
+}
+
+pub fn set_turbo(on: bool) -> Result<bool, Errcode> {
+    match MAILBOX.query(RpiMailboxTag::SetTurbo(on))? {
+        RpiMailboxReply::TurboState(on) => Ok(on),
+        _ => unreachable!("Reply to a tag we did not send"),
+    }
+}
+
+fn query_rate(clock: Clock, tag: RpiMailboxTag) -> Result<u32, Errcode> {
+    match MAILBOX.query(tag)? {
+        RpiMailboxReply::TagClockRate { freq: 0, .. } => {
+            Err(Errcode::ClockNotFound(clock.tag_id() as u32))
+        }
+        RpiMailboxReply::TagClockRate { freq, .. } => Ok(freq),
+        _ => unreachable!("Reply to a tag we did not send"),
+    }
+}
@@ -1,44 +1,328 @@
+use alloc::boxed::Box;
 use core::fmt::Write;
 
-use crate::sync::NullLock;
+use crate::errors::Errcode;
+use crate::screen::{Screen, TextStyle};
+use crate::sync::SpinLock;
+use crate::{print, println};
+
+pub const MAX_SINKS: usize = 4;
+pub const MAX_COMMANDS: usize = 16;
+const TAB_WIDTH: u32 = 4;
+const MAX_LINE_LENGTH: usize = 128;
 
 pub static CONSOLE: Console = Console::init();
 
-pub struct Console(NullLock<ConsoleInner>);
+static UART_SINK: UartSink = UartSink;
+
+static COMMANDS: SpinLock<[Option<Command>; MAX_COMMANDS]> = SpinLock::new([None; MAX_COMMANDS]);
+static INPUT: SpinLock<LineBuffer> = SpinLock::new(LineBuffer::new());
+
+/// Sink registered by default, the serial port.
+pub const UART_SINK_ID: SinkId = SinkId(0);
+
+// Destination of the console output
+//    Called with the console lock held and IRQs masked, so it must not print itself.
+pub trait ConsoleSink: Sync {
+    fn write_str(&self, s: &str);
+}
+
+/// Handle on a registered sink.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SinkId(usize);
+
+// Everything printed goes to every enabled sink
+pub struct Console(SpinLock<ConsoleInner>);
 
 impl Console {
     pub const fn init() -> Console {
-        Console(NullLock::new(ConsoleInner::init()))
+        Console(SpinLock::new_irqsafe(ConsoleInner::init()))
     }
 
     pub fn write_fmt(&self, args: core::fmt::Arguments) -> core::fmt::Result {
         self.0.lock(|console| console.write_fmt(args))
     }
+
+    // New sinks start enabled
+    pub fn register_sink(&self, sink: &'static dyn ConsoleSink) -> Result<SinkId, Errcode> {
+        self.0.lock(|console| {
+            let idx = console
+                .sinks
+                .iter()
+                .position(|s| s.is_none())
+                .ok_or(Errcode::ConsoleTooManySinks)?;
+            console.sinks[idx] = Some(SinkEntry {
+                sink,
+                enabled: true,
+            });
+            Ok(SinkId(idx))
+        })
+    }
+
+    pub fn unregister_sink(&self, id: SinkId) -> Result<(), Errcode> {
+        self.0.lock(|console| {
+            console.entry(id)?;
+            console.sinks[id.0] = None;
+            Ok(())
+        })
+    }
+
+    pub fn enable_sink(&self, id: SinkId) -> Result<(), Errcode> {
+        self.0
+            .lock(|console| console.entry(id).map(|entry| entry.enabled = true))
+    }
+
+    pub fn disable_sink(&self, id: SinkId) -> Result<(), Errcode> {
+        self.0
+            .lock(|console| console.entry(id).map(|entry| entry.enabled = false))
+    }
+}
+
+#[derive(Clone, Copy)]
+struct SinkEntry {
+    sink: &'static dyn ConsoleSink,
+    enabled: bool,
 }
 
-struct ConsoleInner;
+struct ConsoleInner {
+    sinks: [Option<SinkEntry>; MAX_SINKS],
+}
 
 impl ConsoleInner {
     pub const fn init() -> ConsoleInner {
-        ConsoleInner {}
+        let mut sinks = [None; MAX_SINKS];
+        sinks[UART_SINK_ID.0] = Some(SinkEntry {
+            sink: &UART_SINK,
+            enabled: true,
+        });
+        ConsoleInner { sinks }
+    }
+
+    fn entry(&mut self, id: SinkId) -> Result<&mut SinkEntry, Errcode> {
+        self.sinks
+            .get_mut(id.0)
+            .and_then(|entry| entry.as_mut())
+            .ok_or(Errcode::ConsoleInvalidSink)
     }
 }
 
 impl core::fmt::Write for ConsoleInner {
-    fn write_char(&mut self, c: char) -> core::fmt::Result {
-        let uart = &crate::drivers::UART;
-        uart.write_char(c);
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for entry in self.sinks.iter().flatten().filter(|entry| entry.enabled) {
+            entry.sink.write_str(s);
+        }
         Ok(())
     }
+}
 
-    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+struct UartSink;
+
+impl ConsoleSink for UartSink {
+    fn write_str(&self, s: &str) {
+        let uart = &crate::drivers::UART;
         for c in s.chars() {
             if c == '\n' {
-                self.write_char('\r')?;
+                uart.write_char('\r');
             }
-            self.write_char(c)?;
+            uart.write_char(c);
         }
+    }
+}
+
+// Text console on the screen, scrolls when the last line is full
+pub struct FramebufferConsole(SpinLock<FramebufferConsoleInner>);
+
+struct FramebufferConsoleInner {
+    screen: Screen,
+    style: TextStyle,
+    line: u32,
+    column: u32,
+}
+
+impl FramebufferConsole {
+    pub fn new(screen: Screen, style: TextStyle) -> FramebufferConsole {
+        screen.fill_screen(style.background());
+        FramebufferConsole(SpinLock::new(FramebufferConsoleInner {
+            screen,
+            style,
+            line: 0,
+            column: 0,
+        }))
+    }
+
+    pub fn set_style(&self, style: TextStyle) {
+        self.0.lock(|console| console.style = style);
+    }
+}
+
+impl ConsoleSink for FramebufferConsole {
+    fn write_str(&self, s: &str) {
+        self.0
+            .lock(|console| s.chars().for_each(|c| console.put_char(c)));
+    }
+}
+
+impl FramebufferConsoleInner {
+    fn put_char(&mut self, c: char) {
+        match c {
+            '\n' => self.new_line(),
+            '\r' => self.column = 0,
+            '\t' => {
+                // The next stop may be past the end of the line, the spaces must not wrap
+                let next = ((self.column / TAB_WIDTH + 1) * TAB_WIDTH).min(self.screen.columns());
+                while self.column < next {
+                    self.put_char(' ');
+                }
+            }
+            c => {
+                if self.column >= self.screen.columns() {
+                    self.new_line();
+                }
+                self.screen.draw_char(self.line, self.column, c, self.style);
+                self.column += 1;
+            }
+        }
+    }
+
+    fn new_line(&mut self) {
+        self.column = 0;
+        if self.line + 1 < self.screen.lines() {
+            self.line += 1;
+        } else {
+            self.screen.scroll_up(1, self.style.background());
+        }
+    }
+}
+
+// Show the console output on this screen as well, for as long as the kernel runs
+pub fn attach_screen(screen: Screen, style: TextStyle) -> Result<SinkId, Errcode> {
+    let console: &'static FramebufferConsole =
+        Box::leak(Box::new(FramebufferConsole::new(screen, style)));
+    CONSOLE.register_sink(console)
+}
+
+// Command typed on the serial console
+//    `run` gets the rest of the line after the name, trimmed. Commands run from `poll_input`,
+//    never inside an IRQ handler, so they are free to print or use the mailbox.
+#[derive(Clone, Copy)]
+pub struct Command {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub run: fn(&str),
+}
+
+// `help` is always there, it lists the other ones
+static HELP_COMMAND: Command = Command {
+    name: "help",
+    help: "list the available commands",
+    run: |_| {
+        println!("{:<12}{}", HELP_COMMAND.name, HELP_COMMAND.help);
+        COMMANDS.lock(|commands| {
+            for command in commands.iter().flatten() {
+                println!("{:<12}{}", command.name, command.help);
+            }
+        });
+    },
+};
+
+pub fn register_command(command: Command) -> Result<(), Errcode> {
+    COMMANDS.lock(|commands| {
+        if command.name == HELP_COMMAND.name
+            || commands.iter().flatten().any(|c| c.name == command.name)
+        {
+            return Err(Errcode::ConsoleCommandExists);
+        }
+        let slot = commands
+            .iter_mut()
+            .find(|c| c.is_none())
+            .ok_or(Errcode::ConsoleTooManyCommands)?;
+        *slot = Some(command);
         Ok(())
+    })
+}
+
+// Run the command named by the first word of the line, an empty line does nothing
+pub fn run_command(line: &str) -> Result<(), Errcode> {
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(());
+    }
+    let (name, args) = line.split_once(' ').unwrap_or((line, ""));
+    let command = if name == HELP_COMMAND.name {
+        Some(HELP_COMMAND)
+    } else {
+        COMMANDS.lock(|commands| commands.iter().flatten().find(|c| c.name == name).copied())
+    };
+    // Not run under the lock, the command may register others
+    let command = command.ok_or(Errcode::ConsoleUnknownCommand)?;
+    (command.run)(args.trim());
+    Ok(())
+}
+
+// Read what was typed on the serial port so far, without blocking
+//    Characters are echoed back, a complete line is run as a command. To be called regularly
+//    from the main loop.
+pub fn poll_input() {
+    let uart = &crate::drivers::UART;
+    while let Some(byte) = uart.read_byte(false) {
+        match byte {
+            b'\r' | b'\n' => {
+                println!();
+                let (bytes, len) = INPUT.lock(|input| input.take());
+                let line = core::str::from_utf8(&bytes[..len]).unwrap_or("");
+                if let Err(err) = run_command(line) {
+                    println!("{line}: {err}, try 'help'");
+                }
+            }
+            // Backspace and delete, erase the character on the terminal as well
+            0x08 | 0x7F if INPUT.lock(|input| input.pop()) => print!("\x08 \x08"),
+            byte if (byte.is_ascii_graphic() || byte == b' ')
+                && INPUT.lock(|input| input.push(byte)) =>
+            {
+                print!("{}", byte as char)
+            }
+            // Control characters, or nothing to erase, or the line is full
+            _ => {}
+        }
+    }
+}
+
+// Line being typed on the serial console
+struct LineBuffer {
+    bytes: [u8; MAX_LINE_LENGTH],
+    len: usize,
+}
+
+impl LineBuffer {
+    const fn new() -> LineBuffer {
+        LineBuffer {
+            bytes: [0; MAX_LINE_LENGTH],
+            len: 0,
+        }
+    }
+
+    // False when the line is full
+    fn push(&mut self, byte: u8) -> bool {
+        if self.len == MAX_LINE_LENGTH {
+            return false;
+        }
+        self.bytes[self.len] = byte;
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> bool {
+        if self.len == 0 {
+            return false;
+        }
+        self.len -= 1;
+        true
+    }
+
+    fn take(&mut self) -> ([u8; MAX_LINE_LENGTH], usize) {
+        let line = (self.bytes, self.len);
+        self.len = 0;
+        line
     }
 }
 
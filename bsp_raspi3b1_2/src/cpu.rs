@@ -1,4 +1,8 @@
 use aarch64_cpu::asm;
+use aarch64_cpu::registers::{DAIF, MPIDR_EL1};
+use tock_registers::interfaces::{ReadWriteable, Readable, Writeable};
+
+pub const NB_CORES: usize = 4;
 
 #[inline(always)]
 pub fn wait_forever() -> ! {
@@ -10,10 +14,33 @@ pub fn wait_forever() -> ! {
 
 pub use asm::nop;
 
-/// Spin for `n` cycles.
+/// Index of the core executing this code.
 #[inline(always)]
-pub fn spin_for_cycles(n: usize) {
-    for _ in 0..n {
-        asm::nop();
-    }
+pub fn core_id() -> usize {
+    (MPIDR_EL1.get() & 0b11) as usize
+}
+
+/// Let IRQs reach the core.
+#[inline(always)]
+pub fn irq_unmask() {
+    DAIF.modify(DAIF::I::Unmasked);
+}
+
+/// Prevent IRQs from reaching the core, they stay pending in the controller.
+#[inline(always)]
+pub fn irq_mask() {
+    DAIF.modify(DAIF::I::Masked);
+}
+
+/// Mask IRQs and FIQs, returns the previous state to give to `irq_restore`.
+#[inline(always)]
+pub fn irq_save() -> u64 {
+    let daif = DAIF.get();
+    DAIF.modify(DAIF::I::Masked + DAIF::F::Masked);
+    daif
+}
+
+#[inline(always)]
+pub fn irq_restore(daif: u64) {
+    DAIF.set(daif);
 }
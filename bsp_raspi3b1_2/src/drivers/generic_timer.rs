@@ -0,0 +1,170 @@
+use core::ops::{Add, Sub};
+use core::time::Duration;
+
+use aarch64_cpu::asm::barrier;
+use aarch64_cpu::registers::{CNTFRQ_EL0, CNTPCT_EL0, CNTP_CTL_EL0, CNTP_CVAL_EL0};
+use tock_registers::interfaces::{Readable, Writeable};
+
+use crate::cpu::{core_id, NB_CORES};
+use crate::sync::SpinLock;
+
+use super::irq::IRQ;
+
+const NANOSEC_PER_SEC: u128 = 1_000_000_000;
+
+/// Function called (in IRQ context) on the core whose deadline expired.
+pub type DeadlineHandler = fn();
+
+pub static GENERIC_TIMER: GenericTimerDriver = GenericTimerDriver::init();
+
+// AArch64 generic timer, one EL1 physical timer per core
+//    The counter is shared by all the cores and runs at a fixed frequency (19.2 MHz on the board),
+//    independently of the CPU clock, so it is the reference for precise delays.
+pub struct GenericTimerDriver {
+    handlers: SpinLock<[Option<DeadlineHandler>; NB_CORES]>,
+}
+
+impl GenericTimerDriver {
+    const fn init() -> GenericTimerDriver {
+        GenericTimerDriver {
+            handlers: SpinLock::new_irqsafe([None; NB_CORES]),
+        }
+    }
+
+    // Call `handler` on this core once `deadline` is reached
+    //    Replaces the previous deadline of this core. This is a one-shot, the handler can arm a
+    //    new deadline to get called periodically.
+    pub fn arm_deadline(&self, deadline: Instant, handler: DeadlineHandler) {
+        let core = core_id();
+        self.handlers.lock(|h| h[core] = Some(handler));
+        CNTP_CVAL_EL0.set(deadline.0);
+        CNTP_CTL_EL0.write(CNTP_CTL_EL0::ENABLE::SET + CNTP_CTL_EL0::IMASK::CLEAR);
+        IRQ.enable_local_timer_irq();
+    }
+
+    pub fn arm_in(&self, duration: Duration, handler: DeadlineHandler) {
+        self.arm_deadline(Instant::now() + duration, handler);
+    }
+
+    // Cancel the deadline of this core, if any
+    pub fn disarm(&self) {
+        let core = core_id();
+        CNTP_CTL_EL0.write(CNTP_CTL_EL0::ENABLE::CLEAR);
+        IRQ.disable_local_timer_irq();
+        self.handlers.lock(|h| h[core] = None);
+    }
+
+    // Called from the IRQ handler when the physical timer of this core fired
+    pub(crate) fn handle_irq(&self) {
+        let core = core_id();
+        // Stop the timer first, the condition stays asserted as long as CVAL <= counter
+        CNTP_CTL_EL0.write(CNTP_CTL_EL0::ENABLE::CLEAR);
+        if let Some(handler) = self.handlers.lock(|h| h[core].take()) {
+            handler();
+        }
+    }
+}
+
+/// Frequency of the system counter, in Hz.
+#[inline(always)]
+pub fn frequency() -> u64 {
+    CNTFRQ_EL0.get()
+}
+
+/// Point in time, as a value of the system counter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub fn now() -> Instant {
+        // Prevent the counter from being read ahead of time, out of order
+        barrier::isb(barrier::SY);
+        Instant(CNTPCT_EL0.get())
+    }
+
+    pub fn ticks(&self) -> u64 {
+        self.0
+    }
+
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        ticks_to_duration(self.0.saturating_sub(earlier.0))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        self.0
+            .checked_add(duration_to_ticks(duration)?)
+            .map(Instant)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        self.checked_add(rhs)
+            .expect("Overflow when adding duration to instant")
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+fn ticks_to_duration(ticks: u64) -> Duration {
+    let nanos = u128::from(ticks) * NANOSEC_PER_SEC / u128::from(frequency());
+    Duration::from_nanos(nanos as u64)
+}
+
+fn duration_to_ticks(duration: Duration) -> Option<u64> {
+    let ticks = duration.as_nanos() * u128::from(frequency()) / NANOSEC_PER_SEC;
+    u64::try_from(ticks).ok()
+}
+
+/// Time elapsed since the counter started (power on).
+pub fn uptime() -> Duration {
+    ticks_to_duration(Instant::now().0)
+}
+
+/// Busy-wait for (at least) the given duration.
+pub fn delay(duration: Duration) {
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline {
+        core::hint::spin_loop();
+    }
+}
+
+/// Busy-waiting delay provider, for the embedded-hal drivers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Delay;
+
+impl embedded_hal::delay::DelayNs for Delay {
+    fn delay_ns(&mut self, ns: u32) {
+        delay(Duration::from_nanos(u64::from(ns)));
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        delay(Duration::from_micros(u64::from(us)));
+    }
+
+    fn delay_ms(&mut self, ms: u32) {
+        delay(Duration::from_millis(u64::from(ms)));
+    }
+}
+
+/// Busy-wait for `us` microseconds.
+pub fn delay_us(us: u64) {
+    delay(Duration::from_micros(us));
+}
+
+/// Busy-wait for `ms` milliseconds.
+pub fn delay_ms(ms: u64) {
+    delay(Duration::from_millis(ms));
+}
@@ -1,130 +1,689 @@
+use core::fmt;
+
+use embedded_hal::digital;
 use tock_registers::interfaces::{Readable, Writeable};
 use tock_registers::registers::{ReadOnly, ReadWrite, WriteOnly};
-use tock_registers::{register_bitfields, register_structs};
+use tock_registers::{register_bitfields, register_structs, RegisterLongName};
 
+use crate::console::{self, Command};
+use crate::errors::Errcode;
 use crate::memory::{MMIODerefWrapper, GPIO_BASE};
-use crate::spin_for_cycles;
-use crate::sync::NullLock;
+use crate::print;
+use crate::sync::{bypass_locks, SpinLock};
+
+use super::generic_timer::delay_us;
+use super::irq::{IRQ, IRQ_GPIO_BANK0, IRQ_GPIO_BANK1, IRQ_GPIO_BANK2};
 
 const TOT_NUMBER_GPIO: usize = 54;
+
+// 150 cycles of the VideoCore clock, rounded up even when it runs at 100 MHz
+const PUD_SETUP_US: u64 = 2;
 pub static GPIO: GpioDriver = GpioDriver::init();
 
+/// Function called (in IRQ context) with the number of the pin that saw its event.
+pub type GpioIrqHandler = fn(usize);
+
+// What a pin has to see to raise its IRQ
+//    The synchronous edges are sampled on the system clock, so pulses shorter than a few cycles
+//    are filtered out. The asynchronous ones are not, and catch very short pulses as well.
+//    A level event fires once: its detection is turned off before the handler runs, else the IRQ
+//    would keep firing for as long as the level stays there. Call `set_irq` again to re-arm it,
+//    once the level is gone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpioEvent {
+    RisingEdge,
+    FallingEdge,
+    BothEdges,
+    HighLevel,
+    LowLevel,
+    AsyncRisingEdge,
+    AsyncFallingEdge,
+    AsyncBothEdges,
+}
+
+/// Internal resistor on a pin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pull {
+    None,
+    Up,
+    Down,
+}
+
+/// Driver that set up a pin, and in which mode.
+#[derive(Debug, Clone, Copy)]
+pub struct PinClaim {
+    pub owner: &'static str,
+    // None while the owner left the pin as it found it
+    pub mode: Option<PinMode>,
+    // Held by an `Output` or `Input` handle, which releases it when dropped
+    pub handle: bool,
+}
+
 pub struct GpioDriver {
-    registers: NullLock<GpioRegisters>,
+    registers: SpinLock<GpioRegisters>,
+    irq_handlers: SpinLock<[Option<GpioIrqHandler>; TOT_NUMBER_GPIO]>,
+    claims: SpinLock<[Option<PinClaim>; TOT_NUMBER_GPIO]>,
+    pulls: SpinLock<[Option<Pull>; TOT_NUMBER_GPIO]>,
+    // Level last written to each pin, GPLEV reads the pad which a load can pull elsewhere
+    driven: SpinLock<[bool; TOT_NUMBER_GPIO]>,
 }
 
 impl GpioDriver {
     const fn init() -> GpioDriver {
         GpioDriver {
-            registers: NullLock::new(GpioRegisters::new(GPIO_BASE)),
+            registers: SpinLock::new_irqsafe(GpioRegisters::new(GPIO_BASE)),
+            irq_handlers: SpinLock::new_irqsafe([None; TOT_NUMBER_GPIO]),
+            claims: SpinLock::new([None; TOT_NUMBER_GPIO]),
+            pulls: SpinLock::new([None; TOT_NUMBER_GPIO]),
+            driven: SpinLock::new_irqsafe([false; TOT_NUMBER_GPIO]),
         }
     }
 
+    // First thing of the panic path, the registers may be locked by the code that failed
     pub fn panic_led_on(&self) {
+        bypass_locks();
         self.registers.lock(|reg| {
             reg.GPFSEL2.write(GPFSEL2::FSEL21::Output);
             reg.GPSET0.set(1 << 21);
         })
     }
 
-    pub fn configure(&self, config: &[(usize, PinMode)]) {
+    // Set the function of several pins at once, on behalf of `owner`
+    //    Pins claimed by another owner are refused, the ones of `owner` can be set up again.
+    //    Nothing is changed if one of them is invalid, or if a pin is given twice.
+    //    The typed pins of `Pins` claim their pin when first converted, the other ones are free.
+    //    A converted pin is given to a runtime owner with `Pin::into_runtime`.
+    pub fn configure(
+        &self,
+        owner: &'static str,
+        config: &[(usize, PinMode)],
+    ) -> Result<(), Errcode> {
         let mut used_pins = [false; TOT_NUMBER_GPIO];
-        let mut gpfsel: [u32; 6] = [0, 0, 0, 0, 0, 0];
-        self.registers.lock(|reg| {
-            gpfsel[0] = reg.GPFSEL0.get();
-            gpfsel[1] = reg.GPFSEL1.get();
-            gpfsel[2] = reg.GPFSEL2.get();
-            gpfsel[3] = reg.GPFSEL3.get();
-            gpfsel[4] = reg.GPFSEL4.get();
-            gpfsel[5] = reg.GPFSEL5.get();
-        });
+        for (pin_nb, _) in config {
+            check_pin(*pin_nb)?;
+            if used_pins[*pin_nb] {
+                return Err(Errcode::GpioPinConflict(*pin_nb));
+            }
+            used_pins[*pin_nb] = true;
+        }
+
+        self.claims.lock(|claims| {
+            for (pin_nb, _) in config {
+                check_claim(claims, *pin_nb, owner)?;
+            }
+            self.set_functions(config)?;
+            for (pin_nb, mode) in config {
+                claims[*pin_nb] = Some(PinClaim {
+                    owner,
+                    mode: Some(*mode),
+                    handle: false,
+                });
+            }
+            Ok(())
+        })
+    }
+
+    // Give pins back, they are set as inputs
+    //    Nothing is changed if one of them is not claimed by `owner`, or is held by a handle.
+    pub fn release(&self, owner: &'static str, pins: &[usize]) -> Result<(), Errcode> {
+        for nb in pins {
+            check_pin(*nb)?;
+        }
+        self.claims.lock(|claims| {
+            for nb in pins {
+                match claims[*nb] {
+                    Some(claim) if claim.owner != owner => {
+                        return Err(Errcode::GpioPinNotOwned { pin: *nb, owner })
+                    }
+                    Some(claim) if claim.handle => return Err(Errcode::GpioPinHasHandle(*nb)),
+                    Some(_) => {}
+                    None => return Err(Errcode::GpioPinNotOwned { pin: *nb, owner }),
+                }
+            }
+            for nb in pins {
+                self.set_functions(&[(*nb, PinMode::Input)])?;
+                claims[*nb] = None;
+            }
+            Ok(())
+        })
+    }
+
+    // Who configured the pin last, and how
+    pub fn claim(&self, pin: usize) -> Result<Option<PinClaim>, Errcode> {
+        check_pin(pin)?;
+        Ok(self.claims.lock(|claims| claims[pin]))
+    }
 
-        for (pin_nb, mode) in config {
-            let fsel_idx = pin_nb / 10;
-            let fsel_offset = (pin_nb % 10) * 3;
-            let val = mode.get_value(*pin_nb);
-            let used_pins_idx: usize = *pin_nb;
-            assert!(
-                !used_pins[used_pins_idx],
-                "Pin already configured for something else"
-            );
-            used_pins[used_pins_idx] = true;
-            gpfsel[fsel_idx] |= val << fsel_offset;
+    // Function of every pin as the hardware has it now, with the owners we know of
+    pub fn pinout(&self) -> Pinout {
+        let claims = self.claims.lock(|claims| *claims);
+        let pulls = self.pulls.lock(|pulls| *pulls);
+        let gpfsel = self.registers.lock(|reg| read_gpfsel(reg));
+        let mut functions = [0; TOT_NUMBER_GPIO];
+        for (pin, function) in functions.iter_mut().enumerate() {
+            *function = (gpfsel[pin / 10] >> ((pin % 10) * 3)) & 0b111;
         }
+        Pinout {
+            functions,
+            claims,
+            pulls,
+        }
+    }
 
-        self.registers.lock(|reg| {
-            reg.GPFSEL0.set(gpfsel[0]);
-            reg.GPFSEL1.set(gpfsel[1]);
-            reg.GPFSEL2.set(gpfsel[2]);
-            reg.GPFSEL3.set(gpfsel[3]);
-            reg.GPFSEL4.set(gpfsel[4]);
-            reg.GPFSEL5.set(gpfsel[5]);
+    // Give a pin of `from` to `to`, with its current function
+    //    A pin `from` does not hold is left alone.
+    pub(super) fn hand_over(&self, pin: usize, from: &'static str, to: &'static str) {
+        self.claims.lock(|claims| {
+            if let Some(claim) = claims[pin].as_mut().filter(|claim| claim.owner == from) {
+                claim.owner = to;
+            }
+        });
+    }
+
+    // Claim a pin for `owner` and set its function, `value` being the one of `mode` on it
+    //    For the typed pins, their conversions are checked when building. An output is driven low
+    //    before switching, so it does not glitch to whatever level was left in the latch.
+    pub(super) fn claim_function(
+        &self,
+        pin: usize,
+        owner: &'static str,
+        mode: PinMode,
+        value: u32,
+    ) -> Result<(), Errcode> {
+        self.claims.lock(|claims| {
+            check_claim(claims, pin, owner)?;
+            if mode == PinMode::Output {
+                self.write_level(pin, false);
+            }
+            self.registers.lock(|reg| {
+                let mut gpfsel = read_gpfsel(reg);
+                set_fsel(&mut gpfsel, pin, value);
+                write_gpfsel(reg, &gpfsel);
+            });
+            claims[pin] = Some(PinClaim {
+                owner,
+                mode: Some(mode),
+                handle: false,
+            });
+            Ok(())
         })
     }
 
-    pub fn set_pin(&self, nb: usize) {
-        assert!(nb < TOT_NUMBER_GPIO);
-        self.registers.lock(|reg| {
-            if nb < 32 {
-                reg.GPSET0.set(1 << nb);
-                reg.GPSET0.set(0);
-            } else {
-                reg.GPSET1.set(1 << (nb - 32));
-                reg.GPSET1.set(0);
+    // Claim `pin` for `owner` as an output driven low, as `configure` would, behind a handle
+    pub fn output(&self, owner: &'static str, pin: usize) -> Result<Output, Errcode> {
+        self.claim_handle(owner, pin, PinMode::Output)?;
+        Ok(Output(pin))
+    }
+
+    // Claim `pin` for `owner` as an input, as `configure` would, behind a handle
+    pub fn input(&self, owner: &'static str, pin: usize) -> Result<Input, Errcode> {
+        self.claim_handle(owner, pin, PinMode::Input)?;
+        Ok(Input(pin))
+    }
+
+    fn claim_handle(&self, owner: &'static str, pin: usize, mode: PinMode) -> Result<(), Errcode> {
+        check_pin(pin)?;
+        self.claims.lock(|claims| {
+            check_claim(claims, pin, owner)?;
+            if mode == PinMode::Output {
+                self.write_level(pin, false);
             }
+            self.set_functions(&[(pin, mode)])?;
+            claims[pin] = Some(PinClaim {
+                owner,
+                mode: Some(mode),
+                handle: true,
+            });
+            Ok(())
         })
     }
 
-    pub fn clear_pin(&self, nb: usize) {
-        assert!(nb < TOT_NUMBER_GPIO);
+    // Set the pin of a dropped handle back to an input, and free it
+    fn drop_handle(&self, pin: usize) {
+        self.claims.lock(|claims| {
+            let _ = self.set_functions(&[(pin, PinMode::Input)]);
+            claims[pin] = None;
+        });
+    }
+
+    // Write the function select fields, nothing is written if one of the modes is invalid
+    fn set_functions(&self, config: &[(usize, PinMode)]) -> Result<(), Errcode> {
         self.registers.lock(|reg| {
-            if nb < 32 {
-                reg.GPCLR0.set(1 << nb);
-                reg.GPCLR0.set(0);
-            } else {
-                reg.GPCLR1.set(1 << (nb - 32));
-                reg.GPCLR1.set(0);
+            let mut gpfsel = read_gpfsel(reg);
+            for (pin_nb, mode) in config {
+                set_fsel(&mut gpfsel, *pin_nb, mode.get_value(*pin_nb)?);
             }
+            write_gpfsel(reg, &gpfsel);
+            Ok(())
         })
     }
 
-    pub fn get_pin_state(&self, nb: usize) -> bool {
-        assert!(nb < TOT_NUMBER_GPIO);
+    pub fn set_pin(&self, nb: usize) -> Result<(), Errcode> {
+        check_pin(nb)?;
+        self.write_level(nb, true);
+        Ok(())
+    }
+
+    pub fn clear_pin(&self, nb: usize) -> Result<(), Errcode> {
+        check_pin(nb)?;
+        self.write_level(nb, false);
+        Ok(())
+    }
+
+    pub fn get_pin_state(&self, nb: usize) -> Result<bool, Errcode> {
+        check_pin(nb)?;
+        Ok(self.read_level(nb))
+    }
+
+    // The pin number has to be valid
+    pub(super) fn write_level(&self, nb: usize, high: bool) {
+        self.driven.lock(|driven| {
+            self.registers.lock(|reg| match (high, nb < 32) {
+                (true, true) => reg.GPSET0.set(1 << nb),
+                (true, false) => reg.GPSET1.set(1 << (nb - 32)),
+                (false, true) => reg.GPCLR0.set(1 << nb),
+                (false, false) => reg.GPCLR1.set(1 << (nb - 32)),
+            });
+            driven[nb] = high;
+        });
+    }
+
+    // Level last written with `write_level`, low for a pin never written since boot
+    //    The pin number has to be valid.
+    pub(super) fn driven_level(&self, nb: usize) -> bool {
+        self.driven.lock(|driven| driven[nb])
+    }
+
+    // The pin number has to be valid
+    pub(super) fn read_level(&self, nb: usize) -> bool {
         self.registers.lock(|reg| {
             if nb < 32 {
-                (reg.GPLEV0.get() | (1 << nb)) > 0
+                (reg.GPLEV0.get() & (1 << nb)) != 0
             } else {
-                (reg.GPLEV1.get() | (1 << (nb - 32))) > 0
+                (reg.GPLEV1.get() & (1 << (nb - 32))) != 0
             }
         })
     }
 
-    pub fn disable_pud(&self, pins: &[usize]) {
-        assert!(pins.iter().all(|nb| *nb < TOT_NUMBER_GPIO));
+    pub fn disable_pud(&self, pins: &[usize]) -> Result<(), Errcode> {
+        self.set_pull(pins, Pull::None)
+    }
+
+    // Set the internal pull resistor of several pins at once
+    //    The sequence of the datasheet: set GPPUD, wait 150 cycles of the VideoCore, clock it into
+    //    the pins with GPPUDCLK, wait again, then remove both. The cycles are counted on the
+    //    generic timer, as nops depend on the ARM clock.
+    pub fn set_pull(&self, pins: &[usize], pull: Pull) -> Result<(), Errcode> {
+        for nb in pins {
+            check_pin(*nb)?;
+        }
+        self.write_pull(pins, pull);
+        Ok(())
+    }
+
+    // The pin numbers have to be valid
+    pub(super) fn write_pull(&self, pins: &[usize], pull: Pull) {
+        let mut val0 = 0u32;
+        let mut val1 = 0u32;
+        for nb in pins {
+            if *nb < 32 {
+                val0 |= 1 << nb;
+            } else {
+                val1 |= 1 << (nb - 32);
+            }
+        }
+        self.pulls.lock(|pulls| {
+            self.registers.lock(|reg| {
+                reg.GPPUD.write(match pull {
+                    Pull::None => GPPUD::PUD::Off,
+                    Pull::Up => GPPUD::PUD::PullUp,
+                    Pull::Down => GPPUD::PUD::PullDown,
+                });
+                delay_us(PUD_SETUP_US);
+                reg.GPPUDCLK0.set(val0);
+                reg.GPPUDCLK1.set(val1);
+                delay_us(PUD_SETUP_US);
+                reg.GPPUD.write(GPPUD::PUD::Off);
+                reg.GPPUDCLK0.set(0);
+                reg.GPPUDCLK1.set(0);
+            });
+            for nb in pins {
+                pulls[*nb] = Some(pull);
+            }
+        });
+    }
+
+    // Last pull set on the pin, None if it was not set since boot
+    //    The hardware cannot be asked: a pin never set keeps its reset default, or whatever the
+    //    firmware set from config.txt.
+    pub fn pull(&self, pin: usize) -> Result<Option<Pull>, Errcode> {
+        check_pin(pin)?;
+        Ok(self.pulls.lock(|pulls| pulls[pin]))
+    }
+
+    // Call `handler` every time `event` happens on the pin, replacing any previous one
+    //    The pin keeps its function, it is usually configured as an input first.
+    pub fn set_irq(
+        &self,
+        pin: usize,
+        event: GpioEvent,
+        handler: GpioIrqHandler,
+    ) -> Result<(), Errcode> {
+        check_pin(pin)?;
+        self.set_detection(pin, None);
+        self.irq_handlers.lock(|h| h[pin] = Some(handler));
+        self.set_detection(pin, Some(event));
+
+        // Every bank goes to the same handler, which looks at all the pins
+        let bank_irq = bank_irq(pin);
+        IRQ.register_irq(bank_irq, || GPIO.handle_irq())?;
+        IRQ.enable_irq(bank_irq)
+    }
+
+    // Stop detecting events on the pin
+    //    The bank IRQ stays enabled, other pins of the bank may still use it.
+    pub fn clear_irq(&self, pin: usize) -> Result<(), Errcode> {
+        check_pin(pin)?;
+        self.set_detection(pin, None);
+        self.irq_handlers.lock(|h| h[pin] = None);
+        Ok(())
+    }
+
+    // Enable the detection of `event` only, none at all if None, and forget any event already seen
+    fn set_detection(&self, pin: usize, event: Option<GpioEvent>) {
+        let (rising, falling, high, low, async_rising, async_falling) = match event {
+            None => (false, false, false, false, false, false),
+            Some(GpioEvent::RisingEdge) => (true, false, false, false, false, false),
+            Some(GpioEvent::FallingEdge) => (false, true, false, false, false, false),
+            Some(GpioEvent::BothEdges) => (true, true, false, false, false, false),
+            Some(GpioEvent::HighLevel) => (false, false, true, false, false, false),
+            Some(GpioEvent::LowLevel) => (false, false, false, true, false, false),
+            Some(GpioEvent::AsyncRisingEdge) => (false, false, false, false, true, false),
+            Some(GpioEvent::AsyncFallingEdge) => (false, false, false, false, false, true),
+            Some(GpioEvent::AsyncBothEdges) => (false, false, false, false, true, true),
+        };
         self.registers.lock(|reg| {
-            reg.GPPUD.set(0);
-            spin_for_cycles(1500);
-            let mut val0 = 0u32;
-            let mut val1 = 0u32;
-            for nb in pins.iter() {
-                if *nb < 32 {
-                    val0 |= 1 << nb;
-                } else {
-                    val1 |= 1 << (nb - 32);
+            update_pin_bit(&reg.GPREN0, &reg.GPREN1, pin, rising);
+            update_pin_bit(&reg.GPFEN0, &reg.GPFEN1, pin, falling);
+            update_pin_bit(&reg.GPHEN0, &reg.GPHEN1, pin, high);
+            update_pin_bit(&reg.GPLEN0, &reg.GPLEN1, pin, low);
+            update_pin_bit(&reg.GPAREN0, &reg.GPAREN1, pin, async_rising);
+            update_pin_bit(&reg.GPAFEN0, &reg.GPAFEN1, pin, async_falling);
+            // Write 1 to clear
+            if pin < 32 {
+                reg.GPEDS0.set(1 << pin);
+            } else {
+                reg.GPEDS1.set(1 << (pin - 32));
+            }
+        });
+    }
+
+    // Called inside the GPIO bank IRQ handlers
+    //    The events are acknowledged before running the handlers, so an edge happening meanwhile
+    //    raises the IRQ again instead of being lost. Level detections are disabled first, they
+    //    would set their event again right away.
+    fn handle_irq(&self) {
+        let mut events = self.registers.lock(|reg| {
+            let events = u64::from(reg.GPEDS0.get()) | (u64::from(reg.GPEDS1.get()) << 32);
+            reg.GPHEN0.set(reg.GPHEN0.get() & !(events as u32));
+            reg.GPHEN1.set(reg.GPHEN1.get() & !((events >> 32) as u32));
+            reg.GPLEN0.set(reg.GPLEN0.get() & !(events as u32));
+            reg.GPLEN1.set(reg.GPLEN1.get() & !((events >> 32) as u32));
+            reg.GPEDS0.set(events as u32);
+            reg.GPEDS1.set((events >> 32) as u32);
+            events
+        });
+        while events != 0 {
+            let pin = events.trailing_zeros() as usize;
+            events &= !(1 << pin);
+            // Do not hold the lock while running the handler, it may change the IRQs
+            if let Some(handler) = self.irq_handlers.lock(|h| h.get(pin).copied().flatten()) {
+                handler(pin);
+            }
+        }
+    }
+}
+
+// Handles on a single pin configured at runtime, for the embedded-hal traits
+//    There is at most one handle per pin, see `GpioDriver::output` and `GpioDriver::input`.
+pub struct Output(usize);
+
+pub struct Input(usize);
+
+impl Output {
+    pub fn pin(&self) -> usize {
+        self.0
+    }
+
+    pub fn set_high(&mut self) {
+        GPIO.write_level(self.0, true);
+    }
+
+    pub fn set_low(&mut self) {
+        GPIO.write_level(self.0, false);
+    }
+
+    pub fn is_set_high(&self) -> bool {
+        GPIO.driven_level(self.0)
+    }
+}
+
+impl Input {
+    pub fn pin(&self) -> usize {
+        self.0
+    }
+
+    pub fn set_pull(&mut self, pull: Pull) {
+        GPIO.write_pull(&[self.0], pull);
+    }
+
+    pub fn is_high(&self) -> bool {
+        GPIO.read_level(self.0)
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        GPIO.drop_handle(self.0);
+    }
+}
+
+impl Drop for Input {
+    fn drop(&mut self) {
+        GPIO.drop_handle(self.0);
+    }
+}
+
+impl digital::ErrorType for Output {
+    type Error = Errcode;
+}
+
+impl digital::ErrorType for Input {
+    type Error = Errcode;
+}
+
+impl digital::OutputPin for Output {
+    fn set_low(&mut self) -> Result<(), Errcode> {
+        Output::set_low(self);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Errcode> {
+        Output::set_high(self);
+        Ok(())
+    }
+}
+
+impl digital::StatefulOutputPin for Output {
+    fn is_set_high(&mut self) -> Result<bool, Errcode> {
+        Ok(Output::is_set_high(self))
+    }
+
+    fn is_set_low(&mut self) -> Result<bool, Errcode> {
+        Ok(!Output::is_set_high(self))
+    }
+}
+
+impl digital::InputPin for Input {
+    fn is_high(&mut self) -> Result<bool, Errcode> {
+        Ok(Input::is_high(self))
+    }
+
+    fn is_low(&mut self) -> Result<bool, Errcode> {
+        Ok(!Input::is_high(self))
+    }
+}
+
+// `pinout` on the serial console
+pub(crate) fn register_command() -> Result<(), Errcode> {
+    console::register_command(Command {
+        name: "pinout",
+        help: "show the function and owner of every GPIO pin",
+        run: |_| print!("{}", GPIO.pinout()),
+    })
+}
+
+// IRQ raised by the bank of the pin
+fn bank_irq(pin: usize) -> usize {
+    match pin {
+        0..=27 => IRQ_GPIO_BANK0,
+        28..=45 => IRQ_GPIO_BANK1,
+        _ => IRQ_GPIO_BANK2,
+    }
+}
+
+// Set or clear the bit of the pin in a pair of per-pin registers
+fn update_pin_bit<A: RegisterLongName, B: RegisterLongName>(
+    low: &ReadWrite<u32, A>,
+    high: &ReadWrite<u32, B>,
+    pin: usize,
+    set: bool,
+) {
+    if pin < 32 {
+        let bit = 1 << pin;
+        let value = low.get();
+        low.set(if set { value | bit } else { value & !bit });
+    } else {
+        let bit = 1 << (pin - 32);
+        let value = high.get();
+        high.set(if set { value | bit } else { value & !bit });
+    }
+}
+
+/// Snapshot of the functions of all the pins, see `GpioDriver::pinout`.
+pub struct Pinout {
+    functions: [u32; TOT_NUMBER_GPIO],
+    claims: [Option<PinClaim>; TOT_NUMBER_GPIO],
+    pulls: [Option<Pull>; TOT_NUMBER_GPIO],
+}
+
+impl Pinout {
+    // Function select field of the pin, as read from GPFSEL
+    pub fn function(&self, pin: usize) -> Option<u32> {
+        self.functions.get(pin).copied()
+    }
+
+    pub fn claim(&self, pin: usize) -> Option<PinClaim> {
+        self.claims.get(pin).copied().flatten()
+    }
+
+    pub fn pull(&self, pin: usize) -> Option<Pull> {
+        self.pulls.get(pin).copied().flatten()
+    }
+}
+
+// One line per pin: function in the hardware, pull if set, then owner and mode from the registry
+//    A mode that does not match the hardware any more means someone wrote GPFSEL behind the back
+//    of the registry.
+impl fmt::Display for Pinout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (pin, function) in self.functions.iter().enumerate() {
+            let pull = match self.pulls[pin] {
+                None => "",
+                Some(Pull::None) => "NONE",
+                Some(Pull::Up) => "UP",
+                Some(Pull::Down) => "DOWN",
+            };
+            write!(f, "GPIO{pin:<2} {:<4} {pull:<4}", function_name(*function))?;
+            if let Some(claim) = self.claims[pin] {
+                write!(f, " {}", claim.owner)?;
+                if let Some(mode) = claim.mode {
+                    write!(f, " ({mode:?})")?;
+                    if mode.get_value(pin).ok() != Some(*function) {
+                        write!(f, " changed")?;
+                    }
                 }
             }
-            reg.GPPUDCLK0.set(val0);
-            reg.GPPUDCLK1.set(val1);
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
 
-            spin_for_cycles(1500);
-            reg.GPPUD.set(0);
-            reg.GPPUDCLK0.set(0);
-            reg.GPPUDCLK1.set(0);
-        })
+fn function_name(function: u32) -> &'static str {
+    match function {
+        0b000 => "IN",
+        0b001 => "OUT",
+        0b100 => "ALT0",
+        0b101 => "ALT1",
+        0b110 => "ALT2",
+        0b111 => "ALT3",
+        0b011 => "ALT4",
+        _ => "ALT5",
+    }
+}
+
+fn set_fsel(gpfsel: &mut [u32; 6], pin: usize, value: u32) {
+    let offset = (pin % 10) * 3;
+    gpfsel[pin / 10] &= !(0b111 << offset);
+    gpfsel[pin / 10] |= value << offset;
+}
+
+fn write_gpfsel(reg: &GpioRegisters, gpfsel: &[u32; 6]) {
+    reg.GPFSEL0.set(gpfsel[0]);
+    reg.GPFSEL1.set(gpfsel[1]);
+    reg.GPFSEL2.set(gpfsel[2]);
+    reg.GPFSEL3.set(gpfsel[3]);
+    reg.GPFSEL4.set(gpfsel[4]);
+    reg.GPFSEL5.set(gpfsel[5]);
+}
+
+fn read_gpfsel(reg: &GpioRegisters) -> [u32; 6] {
+    [
+        reg.GPFSEL0.get(),
+        reg.GPFSEL1.get(),
+        reg.GPFSEL2.get(),
+        reg.GPFSEL3.get(),
+        reg.GPFSEL4.get(),
+        reg.GPFSEL5.get(),
+    ]
+}
+
+// A pin can be claimed by `owner` if it is free, or already its own without a handle on it
+fn check_claim(
+    claims: &[Option<PinClaim>; TOT_NUMBER_GPIO],
+    pin: usize,
+    owner: &'static str,
+) -> Result<(), Errcode> {
+    match claims[pin] {
+        Some(claim) if claim.owner != owner => Err(Errcode::GpioPinOwned {
+            pin,
+            owner: claim.owner,
+        }),
+        Some(claim) if claim.handle => Err(Errcode::GpioPinHasHandle(pin)),
+        _ => Ok(()),
+    }
+}
+
+fn check_pin(nb: usize) -> Result<(), Errcode> {
+    if nb < TOT_NUMBER_GPIO {
+        Ok(())
+    } else {
+        Err(Errcode::GpioInvalidPin(nb))
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PinMode {
     Input,
     Output,
@@ -163,181 +722,190 @@ pub enum PinMode {
 }
 
 impl PinMode {
-    pub fn get_value(&self, pin_nb: usize) -> u32 {
-        match self {
+    // Value of the function select field for this mode on this pin
+    //    Each alternate function is only available on some pins.
+    //    Const, so that the typed pins check their conversions when building.
+    pub const fn get_value(&self, pin_nb: usize) -> Result<u32, Errcode> {
+        let value = match self {
             PinMode::Input => 0b000,
             PinMode::Output => 0b001,
             PinMode::BscSda(n) => match (n, pin_nb) {
                 (0, 0) | (1, 2) | (0, 28) => 0b100,
                 (0, 44) => 0b101,
                 (1, 44) => 0b110,
-                arg => unreachable!("BscSda {arg:?}"),
+                _ => return Err(self.invalid_on(pin_nb)),
             },
             PinMode::BscScl(n) => match (n, pin_nb) {
                 (0, 1) | (1, 3) | (0, 29) => 0b100,
                 (0, 45) => 0b101,
                 (1, 45) => 0b110,
-                arg => unreachable!("BscScl {arg:?}"),
+                _ => return Err(self.invalid_on(pin_nb)),
             },
             PinMode::GpClk(n) => match (n, pin_nb) {
                 (0, 4) | (1, 5) | (2, 6) | (0, 32) | (0, 34) | (1, 42) | (2, 43) | (1, 44) => 0b100,
                 (0, 20) | (1, 21) => 0b010,
-                arg => unreachable!("GpClk {arg:?}"),
+                _ => return Err(self.invalid_on(pin_nb)),
             },
             PinMode::SpiCs(spin, csn) => match (spin, csn, pin_nb) {
                 (0, 1, 7) | (0, 0, 8) | (0, 1, 35) | (0, 0, 36) => 0b100,
                 (1, 0, 18) | (1, 2, 16) | (1, 1, 17) => 0b011,
-                arg => unreachable!("SpiCs {arg:?}"),
+                _ => return Err(self.invalid_on(pin_nb)),
             },
             PinMode::SpiMiso(n) => match (n, pin_nb) {
                 (0, 9) | (0, 37) => 0b100,
                 (1, 19) => 0b011,
-                arg => unreachable!("SpiMiso {arg:?}"),
+                _ => return Err(self.invalid_on(pin_nb)),
             },
             PinMode::SpiMosi(n) => match (n, pin_nb) {
                 (0, 10) | (0, 38) => 0b100,
                 (1, 20) => 0b011,
-                arg => unreachable!("SpiMosi {arg:?}"),
+                _ => return Err(self.invalid_on(pin_nb)),
             },
             PinMode::SpiSclk(n) => match (n, pin_nb) {
                 (0, 39) | (0, 11) => 0b100,
                 (1, 21) => 0b011,
-                arg => unreachable!("SpiSclk {arg:?}"),
+                _ => return Err(self.invalid_on(pin_nb)),
             },
             PinMode::Pwm(n) => match (n, pin_nb) {
                 (0, 12) | (1, 13) | (0, 40) | (1, 41) | (1, 45) => 0b100,
                 (0, 18) | (1, 19) => 0b010,
-                arg => unreachable!("Pwm {arg:?}"),
+                _ => return Err(self.invalid_on(pin_nb)),
             },
             PinMode::UartTxd(n) => match (n, pin_nb) {
                 (0, 14) => 0b100,
                 (0, 32) => 0b111,
                 (0, 36) => 0b110,
                 (1, 14) | (1, 32) | (1, 40) => 0b010,
-                arg => unreachable!("UartTxd {arg:?}"),
+                _ => return Err(self.invalid_on(pin_nb)),
             },
             PinMode::UartRxd(n) => match (n, pin_nb) {
                 (0, 15) => 0b100,
-                (1, 15) | (1, 33) | (1, 40) => 0b010,
+                (1, 15) | (1, 33) | (1, 41) => 0b010,
                 (0, 37) => 0b110,
                 (0, 33) => 0b111,
-                arg => unreachable!("UartRxd {arg:?}"),
+                _ => return Err(self.invalid_on(pin_nb)),
             },
             PinMode::UartCts(n) => match (n, pin_nb) {
                 (0, 16) | (0, 30) => 0b111,
                 (1, 16) | (1, 30) | (1, 43) => 0b010,
                 (0, 39) => 0b110,
-                arg => unreachable!("UartCts {arg:?}"),
+                _ => return Err(self.invalid_on(pin_nb)),
             },
             PinMode::UartRts(n) => match (n, pin_nb) {
                 (0, 17) | (0, 31) => 0b111,
                 (1, 17) | (1, 31) | (1, 42) => 0b010,
                 (0, 38) => 0b110,
-                arg => unreachable!("UartRts {arg:?}"),
+                _ => return Err(self.invalid_on(pin_nb)),
             },
             PinMode::PcmClk => match pin_nb {
                 18 => 0b100,
                 28 => 0b110,
-                arg => unreachable!("PcmClk {arg:?}"),
+                _ => return Err(self.invalid_on(pin_nb)),
             },
             PinMode::PcmFs => match pin_nb {
                 19 => 0b100,
                 29 => 0b110,
-                arg => unreachable!("PcmFs {arg:?}"),
+                _ => return Err(self.invalid_on(pin_nb)),
             },
             PinMode::PcmDin => match pin_nb {
                 20 => 0b100,
                 30 => 0b110,
-                arg => unreachable!("PcmDin {arg:?}"),
+                _ => return Err(self.invalid_on(pin_nb)),
             },
             PinMode::PcmDout => match pin_nb {
                 21 => 0b100,
                 31 => 0b110,
-                arg => unreachable!("PcmDout {arg:?}"),
+                _ => return Err(self.invalid_on(pin_nb)),
             },
             PinMode::SmiSa(n) => match (n, pin_nb) {
                 (5, 0) | (4, 1) | (3, 2) | (2, 3) | (1, 4) | (0, 5) => 0b101,
                 (5, 28) | (4, 29) | (3, 30) | (2, 31) | (1, 32) | (0, 33) => 0b101,
-                arg => unreachable!("SmiSa {arg:?}"),
+                _ => return Err(self.invalid_on(pin_nb)),
             },
             PinMode::SmiSoeNSe => match pin_nb {
                 6 => 0b101,
                 34 => 0b101,
-                arg => unreachable!("SmiSoeNSe {arg:?}"),
+                _ => return Err(self.invalid_on(pin_nb)),
             },
             PinMode::SmiSweNSrwN => match pin_nb {
                 7 => 0b101,
                 35 => 0b101,
-                arg => unreachable!("SmiSweNSrwN {arg:?}"),
+                _ => return Err(self.invalid_on(pin_nb)),
             },
             PinMode::SmiSd(n) => {
-                if (pin_nb == (8 + *n)) || (pin_nb == (36 + *n)) {
+                // SD0 to SD17 on pins 8 to 25, SD0 to SD9 again on pins 36 to 45
+                if (*n <= 17 && pin_nb == 8 + *n) || (*n <= 9 && pin_nb == 36 + *n) {
                     0b101
                 } else {
-                    unreachable!("SmiSd ({n}, {pin_nb})")
+                    return Err(self.invalid_on(pin_nb));
                 }
             }
             PinMode::BscSlSda | PinMode::SpiSlMosi => {
                 if pin_nb == 18 {
                     0b111
                 } else {
-                    unreachable!("{self:?} {pin_nb}")
+                    return Err(self.invalid_on(pin_nb));
                 }
             }
             PinMode::BscSlScl | PinMode::SpiSlSclk => {
                 if pin_nb == 19 {
                     0b111
                 } else {
-                    unreachable!("{self:?} {pin_nb}")
+                    return Err(self.invalid_on(pin_nb));
                 }
             }
             PinMode::SpiSlMiso => {
                 if pin_nb == 20 {
                     0b111
                 } else {
-                    unreachable!("SpiSlMiso {pin_nb}")
+                    return Err(self.invalid_on(pin_nb));
                 }
             }
             PinMode::SpiSlCs => {
                 if pin_nb == 21 {
                     0b111
                 } else {
-                    unreachable!("SpiSlCs {pin_nb}")
+                    return Err(self.invalid_on(pin_nb));
                 }
             }
             PinMode::JtagTrst => {
                 if pin_nb == 22 {
                     0b011
                 } else {
-                    unreachable!("JtagTrst {pin_nb}")
+                    return Err(self.invalid_on(pin_nb));
                 }
             }
             PinMode::JtagRtck => match pin_nb {
                 6 => 0b010,
                 23 => 0b011,
-                arg => unreachable!("JtagRtck {arg}"),
+                _ => return Err(self.invalid_on(pin_nb)),
             },
             PinMode::JtagTdo => match pin_nb {
                 5 => 0b010,
                 24 => 0b011,
-                arg => unreachable!("JtagTdo {arg}"),
+                _ => return Err(self.invalid_on(pin_nb)),
             },
             PinMode::JtagTck => match pin_nb {
                 13 => 0b010,
                 25 => 0b011,
-                arg => unreachable!("JtagTck {arg}"),
+                _ => return Err(self.invalid_on(pin_nb)),
             },
             PinMode::JtagTdi => match pin_nb {
                 4 => 0b010,
                 26 => 0b011,
-                arg => unreachable!("JtagTdi {arg}"),
+                _ => return Err(self.invalid_on(pin_nb)),
             },
             PinMode::JtagTms => match pin_nb {
                 12 => 0b010,
                 27 => 0b011,
-                arg => unreachable!("JtagTms {arg}"),
+                _ => return Err(self.invalid_on(pin_nb)),
             },
-        }
+        };
+        Ok(value)
+    }
+
+    const fn invalid_on(&self, pin: usize) -> Errcode {
+        Errcode::GpioInvalidPinFunction { pin, mode: *self }
     }
 }
 
@@ -0,0 +1,276 @@
+use tock_registers::interfaces::{ReadWriteable, Readable, Writeable};
+use tock_registers::registers::{ReadOnly, ReadWrite, WriteOnly};
+use tock_registers::{register_bitfields, register_structs};
+
+use crate::cpu::{core_id, NB_CORES};
+use crate::errors::Errcode;
+use crate::memory::{MMIODerefWrapper, INTERRUPT_CTRL_BASE, LOCAL_PERIPHERALS_BASE};
+use crate::println;
+use crate::sync::SpinLock;
+
+use super::generic_timer::GENERIC_TIMER;
+
+pub const NB_PERIPHERAL_IRQS: usize = 64;
+
+// Peripheral IRQ numbers routed through the ARM interrupt controller
+//    The ones not listed here are used by the GPU
+pub const IRQ_SYSTEM_TIMER_1: usize = 1;
+pub const IRQ_SYSTEM_TIMER_3: usize = 3;
+pub const IRQ_USB: usize = 9;
+pub const IRQ_AUX: usize = 29;
+pub const IRQ_I2C_SPI_SLAVE: usize = 43;
+pub const IRQ_SMI: usize = 48;
+pub const IRQ_GPIO_BANK0: usize = 49;
+pub const IRQ_GPIO_BANK1: usize = 50;
+pub const IRQ_GPIO_BANK2: usize = 51;
+pub const IRQ_GPIO_ALL: usize = 52;
+pub const IRQ_I2C: usize = 53;
+pub const IRQ_SPI: usize = 54;
+pub const IRQ_PCM: usize = 55;
+pub const IRQ_UART: usize = 57;
+
+/// Function called (in IRQ context) when its peripheral IRQ is pending.
+pub type IrqHandler = fn();
+
+pub static IRQ: IrqDriver = IrqDriver::init();
+
+pub struct IrqDriver {
+    registers: SpinLock<Registers>,
+    local_registers: SpinLock<LocalRegisters>,
+    handlers: SpinLock<[Option<IrqHandler>; NB_PERIPHERAL_IRQS]>,
+}
+
+impl IrqDriver {
+    const fn init() -> IrqDriver {
+        IrqDriver {
+            registers: SpinLock::new_irqsafe(Registers::new(INTERRUPT_CTRL_BASE)),
+            local_registers: SpinLock::new_irqsafe(LocalRegisters::new(LOCAL_PERIPHERALS_BASE)),
+            handlers: SpinLock::new_irqsafe([None; NB_PERIPHERAL_IRQS]),
+        }
+    }
+
+    // Disable every IRQ source and forget about all the handlers
+    //    Peripheral IRQs are routed to core 0
+    pub(crate) fn reset(&self) {
+        self.registers.lock(|reg| {
+            reg.DISABLE_IRQS_1.set(u32::MAX);
+            reg.DISABLE_IRQS_2.set(u32::MAX);
+            reg.DISABLE_BASIC_IRQS.set(u32::MAX);
+        });
+        self.local_registers.lock(|reg| {
+            reg.GPU_INT_ROUTING.write(GPU_INT_ROUTING::IRQ.val(0));
+            reg.CORE_TIMER_IRQCNTL.iter().for_each(|cntl| cntl.set(0));
+        });
+        self.handlers
+            .lock(|h| h.iter_mut().for_each(|slot| *slot = None));
+    }
+
+    // Let the EL1 physical timer of the current core raise IRQs on this core
+    pub(crate) fn enable_local_timer_irq(&self) {
+        let core = core_id();
+        self.local_registers
+            .lock(|reg| reg.CORE_TIMER_IRQCNTL[core].modify(CORE_TIMER_IRQCNTL::CNTPNSIRQ::SET));
+    }
+
+    pub(crate) fn disable_local_timer_irq(&self) {
+        let core = core_id();
+        self.local_registers
+            .lock(|reg| reg.CORE_TIMER_IRQCNTL[core].modify(CORE_TIMER_IRQCNTL::CNTPNSIRQ::CLEAR));
+    }
+
+    // Called from the IRQ exception vector
+    //    Core-local sources first, then the peripherals if the GPU interrupt is routed to us
+    pub(crate) fn handle_irq(&self) {
+        let core = core_id();
+        let source = self
+            .local_registers
+            .lock(|reg| reg.CORE_IRQ_SOURCE[core].extract());
+        if source.is_set(CORE_IRQ_SOURCE::CNTPNSIRQ) {
+            GENERIC_TIMER.handle_irq();
+        }
+        if source.is_set(CORE_IRQ_SOURCE::GPU) {
+            self.handle_pending();
+        }
+    }
+
+    // Set the handler of a peripheral IRQ, replacing any previous one
+    //    The IRQ has to be enabled separately with `enable_irq`
+    pub fn register_irq(&self, nb: usize, handler: IrqHandler) -> Result<(), Errcode> {
+        check_irq(nb)?;
+        self.handlers.lock(|h| h[nb] = Some(handler));
+        Ok(())
+    }
+
+    pub fn unregister_irq(&self, nb: usize) -> Result<(), Errcode> {
+        self.disable_irq(nb)?;
+        self.handlers.lock(|h| h[nb] = None);
+        Ok(())
+    }
+
+    pub fn enable_irq(&self, nb: usize) -> Result<(), Errcode> {
+        check_irq(nb)?;
+        self.registers.lock(|reg| {
+            if nb < 32 {
+                reg.ENABLE_IRQS_1.set(1 << nb);
+            } else {
+                reg.ENABLE_IRQS_2.set(1 << (nb - 32));
+            }
+        });
+        Ok(())
+    }
+
+    pub fn disable_irq(&self, nb: usize) -> Result<(), Errcode> {
+        check_irq(nb)?;
+        self.registers.lock(|reg| {
+            if nb < 32 {
+                reg.DISABLE_IRQS_1.set(1 << nb);
+            } else {
+                reg.DISABLE_IRQS_2.set(1 << (nb - 32));
+            }
+        });
+        Ok(())
+    }
+
+    pub fn is_pending(&self, nb: usize) -> Result<bool, Errcode> {
+        check_irq(nb)?;
+        Ok((self.pending() & (1 << nb)) != 0)
+    }
+
+    fn pending(&self) -> u64 {
+        self.registers.lock(|reg| {
+            u64::from(reg.IRQ_PENDING_1.get()) | (u64::from(reg.IRQ_PENDING_2.get()) << 32)
+        })
+    }
+
+    // Runs the handler of every pending peripheral IRQ. The handler is responsible for
+    //    acknowledging the interrupt on its peripheral, otherwise it will fire again.
+    pub(crate) fn handle_pending(&self) {
+        let mut pending = self.pending();
+        while pending != 0 {
+            let nb = pending.trailing_zeros() as usize;
+            pending &= !(1 << nb);
+
+            // Do not hold the lock while running the handler, it may register other IRQs
+            match self.handlers.lock(|h| h[nb]) {
+                Some(handler) => handler(),
+                None => {
+                    // Nobody will ever acknowledge it, avoid an interrupt storm
+                    //    Cannot fail, the number comes from the pending bits.
+                    let _ = self.disable_irq(nb);
+                    println!("Spurious IRQ {nb} without handler, disabled");
+                }
+            }
+        }
+    }
+}
+
+fn check_irq(nb: usize) -> Result<(), Errcode> {
+    if nb < NB_PERIPHERAL_IRQS {
+        Ok(())
+    } else {
+        Err(Errcode::IrqInvalid(nb))
+    }
+}
+
+register_bitfields! {
+    u32,
+
+    /// Basic pending register, ARM specific interrupts plus a summary of the GPU ones
+    IRQ_BASIC_PENDING [
+        ARM_TIMER OFFSET(0) NUMBITS(1) [],
+        ARM_MAILBOX OFFSET(1) NUMBITS(1) [],
+        ARM_DOORBELL_0 OFFSET(2) NUMBITS(1) [],
+        ARM_DOORBELL_1 OFFSET(3) NUMBITS(1) [],
+        GPU_0_HALTED OFFSET(4) NUMBITS(1) [],
+        GPU_1_HALTED OFFSET(5) NUMBITS(1) [],
+        ILLEGAL_ACCESS_TYPE_1 OFFSET(6) NUMBITS(1) [],
+        ILLEGAL_ACCESS_TYPE_0 OFFSET(7) NUMBITS(1) [],
+
+        /// One or more bits set in pending register 1 (not counting the ones shown here)
+        PENDING_1 OFFSET(8) NUMBITS(1) [],
+
+        /// One or more bits set in pending register 2 (not counting the ones shown here)
+        PENDING_2 OFFSET(9) NUMBITS(1) [],
+
+        /// GPU IRQs 7, 9, 10, 18, 19, 53, 54, 55, 56, 57 and 62
+        GPU_IRQS OFFSET(10) NUMBITS(11) [],
+    ],
+
+    /// FIQ control, only one source can be routed to the FIQ
+    FIQ_CONTROL [
+        SOURCE OFFSET(0) NUMBITS(7) [],
+        ENABLE OFFSET(7) NUMBITS(1) [],
+    ],
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => IRQ_BASIC_PENDING: ReadOnly<u32, IRQ_BASIC_PENDING::Register>),
+        (0x04 => IRQ_PENDING_1: ReadOnly<u32>),
+        (0x08 => IRQ_PENDING_2: ReadOnly<u32>),
+        (0x0C => FIQ_CONTROL: WriteOnly<u32, FIQ_CONTROL::Register>),
+        (0x10 => ENABLE_IRQS_1: WriteOnly<u32>),
+        (0x14 => ENABLE_IRQS_2: WriteOnly<u32>),
+        (0x18 => ENABLE_BASIC_IRQS: WriteOnly<u32>),
+        (0x1C => DISABLE_IRQS_1: WriteOnly<u32>),
+        (0x20 => DISABLE_IRQS_2: WriteOnly<u32>),
+        (0x24 => DISABLE_BASIC_IRQS: WriteOnly<u32>),
+        (0x28 => @END),
+    }
+}
+
+register_bitfields! {
+    u32,
+
+    /// Core routed to the GPU (peripherals) interrupts
+    GPU_INT_ROUTING [
+        IRQ OFFSET(0) NUMBITS(2) [],
+        FIQ OFFSET(2) NUMBITS(2) [],
+    ],
+
+    /// Per-core routing of the ARM generic timer interrupts, FIQ bits take precedence
+    CORE_TIMER_IRQCNTL [
+        CNTPSIRQ OFFSET(0) NUMBITS(1) [],
+        CNTPNSIRQ OFFSET(1) NUMBITS(1) [],
+        CNTHPIRQ OFFSET(2) NUMBITS(1) [],
+        CNTVIRQ OFFSET(3) NUMBITS(1) [],
+        CNTPSFIQ OFFSET(4) NUMBITS(1) [],
+        CNTPNSFIQ OFFSET(5) NUMBITS(1) [],
+        CNTHPFIQ OFFSET(6) NUMBITS(1) [],
+        CNTVFIQ OFFSET(7) NUMBITS(1) [],
+    ],
+
+    /// Per-core pending IRQ sources
+    CORE_IRQ_SOURCE [
+        CNTPSIRQ OFFSET(0) NUMBITS(1) [],
+        CNTPNSIRQ OFFSET(1) NUMBITS(1) [],
+        CNTHPIRQ OFFSET(2) NUMBITS(1) [],
+        CNTVIRQ OFFSET(3) NUMBITS(1) [],
+        MAILBOXES OFFSET(4) NUMBITS(4) [],
+        GPU OFFSET(8) NUMBITS(1) [],
+        PMU OFFSET(9) NUMBITS(1) [],
+        AXI_OUTSTANDING OFFSET(10) NUMBITS(1) [],
+        LOCAL_TIMER OFFSET(11) NUMBITS(1) [],
+    ],
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    LocalRegisterBlock {
+        (0x00 => CONTROL: ReadWrite<u32>),
+        (0x04 => _reserved1),
+        (0x08 => CORE_TIMER_PRESCALER: ReadWrite<u32>),
+        (0x0C => GPU_INT_ROUTING: ReadWrite<u32, GPU_INT_ROUTING::Register>),
+        (0x10 => _reserved2),
+        (0x40 => CORE_TIMER_IRQCNTL: [ReadWrite<u32, CORE_TIMER_IRQCNTL::Register>; NB_CORES]),
+        (0x50 => CORE_MAILBOX_IRQCNTL: [ReadWrite<u32>; NB_CORES]),
+        (0x60 => CORE_IRQ_SOURCE: [ReadOnly<u32, CORE_IRQ_SOURCE::Register>; NB_CORES]),
+        (0x70 => CORE_FIQ_SOURCE: [ReadOnly<u32>; NB_CORES]),
+        (0x80 => @END),
+    }
+}
+
+/// Abstraction for the associated MMIO registers.
+type Registers = MMIODerefWrapper<RegisterBlock>;
+type LocalRegisters = MMIODerefWrapper<LocalRegisterBlock>;
@@ -1,9 +1,16 @@
+pub mod generic_timer;
 pub mod gpio;
+pub mod irq;
+pub mod pins;
 pub mod spi;
+pub mod thermal;
 pub mod timer;
 pub mod uart;
 
+pub use generic_timer::GENERIC_TIMER;
 pub use gpio::GPIO;
+pub use irq::IRQ;
 pub use spi::SPI;
+pub use thermal::THERMAL;
 pub use timer::TIMER;
 pub use uart::UART;
@@ -0,0 +1,450 @@
+use core::fmt;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embedded_hal::digital;
+
+use crate::errors::Errcode;
+
+use super::gpio::{PinMode, Pull, GPIO};
+
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+// Owner of the typed pins in the GPIO registry
+pub const PINS_OWNER: &str = "pins";
+
+// One GPIO pin, with its function as part of its type
+//    Each pin exists once, in `Pins`, and only has the conversions to the functions its hardware
+//    supports: a peripheral given a pin set up for something else does not build. The first
+//    conversion claims the pin for `PINS_OWNER` in the GPIO registry, and is refused if the pin
+//    was configured at runtime by someone else. Until then the pin is free for `configure`.
+pub struct Pin<const N: usize, MODE> {
+    mode: PhantomData<MODE>,
+}
+
+/// Functions a pin can be in.
+pub mod mode {
+    // Whatever the firmware or the chainloader left
+    pub struct Unknown;
+    pub struct Input;
+    pub struct Output;
+
+    macro_rules! alternate_modes {
+        ($($name:ident),* $(,)?) => {
+            $(pub struct $name;)*
+        };
+    }
+
+    alternate_modes! {
+        Bsc0Sda, Bsc0Scl, Bsc1Sda, Bsc1Scl, BscSlSda, BscSlScl,
+        GpClk0, GpClk1, GpClk2,
+        Spi0Cs0, Spi0Cs1, Spi0Miso, Spi0Mosi, Spi0Sclk,
+        Spi1Cs0, Spi1Cs1, Spi1Cs2, Spi1Miso, Spi1Mosi, Spi1Sclk,
+        SpiSlMosi, SpiSlMiso, SpiSlSclk, SpiSlCs,
+        Pwm0, Pwm1,
+        Uart0Txd, Uart0Rxd, Uart0Cts, Uart0Rts,
+        Uart1Txd, Uart1Rxd, Uart1Cts, Uart1Rts,
+        PcmClk, PcmFs, PcmDin, PcmDout,
+        SmiSa0, SmiSa1, SmiSa2, SmiSa3, SmiSa4, SmiSa5, SmiSoeNSe, SmiSweNSrwN,
+        SmiSd0, SmiSd1, SmiSd2, SmiSd3, SmiSd4, SmiSd5, SmiSd6, SmiSd7, SmiSd8,
+        SmiSd9, SmiSd10, SmiSd11, SmiSd12, SmiSd13, SmiSd14, SmiSd15, SmiSd16, SmiSd17,
+        JtagTrst, JtagRtck, JtagTdo, JtagTck, JtagTdi, JtagTms
+    }
+}
+
+impl<const N: usize, MODE> Pin<N, MODE> {
+    const fn new() -> Pin<N, MODE> {
+        Pin { mode: PhantomData }
+    }
+
+    pub const fn number(&self) -> usize {
+        N
+    }
+
+    pub fn into_input(self) -> Result<Pin<N, mode::Input>, Refused<Self>> {
+        self.into_mode(PinMode::Input, 0b000)
+    }
+
+    // Driven low until set otherwise
+    pub fn into_output(self) -> Result<Pin<N, mode::Output>, Refused<Self>> {
+        self.into_mode(PinMode::Output, 0b001)
+    }
+
+    // Give up the typed pin, to be configured at runtime by its number
+    //    A converted pin goes to `owner` in the GPIO registry with its function, one never
+    //    converted is left free.
+    pub fn into_runtime(self, owner: &'static str) -> usize {
+        GPIO.hand_over(N, PINS_OWNER, owner);
+        N
+    }
+
+    // `value` is the one of `mode` on this pin
+    fn into_mode<M>(self, mode: PinMode, value: u32) -> Result<Pin<N, M>, Refused<Self>> {
+        match GPIO.claim_function(N, PINS_OWNER, mode, value) {
+            Ok(()) => Ok(Pin::new()),
+            Err(error) => Err(Refused { pin: self, error }),
+        }
+    }
+}
+
+// Pin(s) given back by a conversion or a driver that refused them, with the reason
+pub struct Refused<P> {
+    pub pin: P,
+    pub error: Errcode,
+}
+
+impl<P> fmt::Debug for Refused<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Refused({:?})", self.error)
+    }
+}
+
+impl<P> fmt::Display for Refused<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+macro_rules! alternate_functions {
+    ($($pin:literal => [$($method:ident: $mode:ident = $pin_mode:expr),* $(,)?]),* $(,)?) => {
+        $(
+            impl<MODE> Pin<$pin, MODE> {
+                $(
+                    pub fn $method(self) -> Result<Pin<$pin, mode::$mode>, Refused<Self>> {
+                        const VALUE: u32 = match $pin_mode.get_value($pin) {
+                            Ok(value) => value,
+                            Err(_) => panic!("Alternate function not available on this pin"),
+                        };
+                        self.into_mode($pin_mode, VALUE)
+                    }
+                )*
+            }
+        )*
+    };
+}
+
+// Alternate functions of each pin, from the BCM2835 peripherals datasheet
+alternate_functions! {
+    0 => [
+        into_bsc0_sda: Bsc0Sda = PinMode::BscSda(0),
+        into_smi_sa5: SmiSa5 = PinMode::SmiSa(5),
+    ],
+    1 => [
+        into_bsc0_scl: Bsc0Scl = PinMode::BscScl(0),
+        into_smi_sa4: SmiSa4 = PinMode::SmiSa(4),
+    ],
+    2 => [
+        into_bsc1_sda: Bsc1Sda = PinMode::BscSda(1),
+        into_smi_sa3: SmiSa3 = PinMode::SmiSa(3),
+    ],
+    3 => [
+        into_bsc1_scl: Bsc1Scl = PinMode::BscScl(1),
+        into_smi_sa2: SmiSa2 = PinMode::SmiSa(2),
+    ],
+    4 => [
+        into_gpclk0: GpClk0 = PinMode::GpClk(0),
+        into_smi_sa1: SmiSa1 = PinMode::SmiSa(1),
+        into_jtag_tdi: JtagTdi = PinMode::JtagTdi,
+    ],
+    5 => [
+        into_gpclk1: GpClk1 = PinMode::GpClk(1),
+        into_smi_sa0: SmiSa0 = PinMode::SmiSa(0),
+        into_jtag_tdo: JtagTdo = PinMode::JtagTdo,
+    ],
+    6 => [
+        into_gpclk2: GpClk2 = PinMode::GpClk(2),
+        into_smi_soe_n_se: SmiSoeNSe = PinMode::SmiSoeNSe,
+        into_jtag_rtck: JtagRtck = PinMode::JtagRtck,
+    ],
+    7 => [
+        into_spi0_cs1: Spi0Cs1 = PinMode::SpiCs(0, 1),
+        into_smi_swe_n_srw_n: SmiSweNSrwN = PinMode::SmiSweNSrwN,
+    ],
+    8 => [
+        into_spi0_cs0: Spi0Cs0 = PinMode::SpiCs(0, 0),
+        into_smi_sd0: SmiSd0 = PinMode::SmiSd(0),
+    ],
+    9 => [
+        into_spi0_miso: Spi0Miso = PinMode::SpiMiso(0),
+        into_smi_sd1: SmiSd1 = PinMode::SmiSd(1),
+    ],
+    10 => [
+        into_spi0_mosi: Spi0Mosi = PinMode::SpiMosi(0),
+        into_smi_sd2: SmiSd2 = PinMode::SmiSd(2),
+    ],
+    11 => [
+        into_spi0_sclk: Spi0Sclk = PinMode::SpiSclk(0),
+        into_smi_sd3: SmiSd3 = PinMode::SmiSd(3),
+    ],
+    12 => [
+        into_pwm0: Pwm0 = PinMode::Pwm(0),
+        into_smi_sd4: SmiSd4 = PinMode::SmiSd(4),
+        into_jtag_tms: JtagTms = PinMode::JtagTms,
+    ],
+    13 => [
+        into_pwm1: Pwm1 = PinMode::Pwm(1),
+        into_smi_sd5: SmiSd5 = PinMode::SmiSd(5),
+        into_jtag_tck: JtagTck = PinMode::JtagTck,
+    ],
+    14 => [
+        into_uart0_txd: Uart0Txd = PinMode::UartTxd(0),
+        into_uart1_txd: Uart1Txd = PinMode::UartTxd(1),
+        into_smi_sd6: SmiSd6 = PinMode::SmiSd(6),
+    ],
+    15 => [
+        into_uart0_rxd: Uart0Rxd = PinMode::UartRxd(0),
+        into_uart1_rxd: Uart1Rxd = PinMode::UartRxd(1),
+        into_smi_sd7: SmiSd7 = PinMode::SmiSd(7),
+    ],
+    16 => [
+        into_spi1_cs2: Spi1Cs2 = PinMode::SpiCs(1, 2),
+        into_uart0_cts: Uart0Cts = PinMode::UartCts(0),
+        into_uart1_cts: Uart1Cts = PinMode::UartCts(1),
+        into_smi_sd8: SmiSd8 = PinMode::SmiSd(8),
+    ],
+    17 => [
+        into_spi1_cs1: Spi1Cs1 = PinMode::SpiCs(1, 1),
+        into_uart0_rts: Uart0Rts = PinMode::UartRts(0),
+        into_uart1_rts: Uart1Rts = PinMode::UartRts(1),
+        into_smi_sd9: SmiSd9 = PinMode::SmiSd(9),
+    ],
+    18 => [
+        into_spi1_cs0: Spi1Cs0 = PinMode::SpiCs(1, 0),
+        into_pwm0: Pwm0 = PinMode::Pwm(0),
+        into_pcm_clk: PcmClk = PinMode::PcmClk,
+        into_smi_sd10: SmiSd10 = PinMode::SmiSd(10),
+        into_bsc_sl_sda: BscSlSda = PinMode::BscSlSda,
+        into_spi_sl_mosi: SpiSlMosi = PinMode::SpiSlMosi,
+    ],
+    19 => [
+        into_spi1_miso: Spi1Miso = PinMode::SpiMiso(1),
+        into_pwm1: Pwm1 = PinMode::Pwm(1),
+        into_pcm_fs: PcmFs = PinMode::PcmFs,
+        into_smi_sd11: SmiSd11 = PinMode::SmiSd(11),
+        into_bsc_sl_scl: BscSlScl = PinMode::BscSlScl,
+        into_spi_sl_sclk: SpiSlSclk = PinMode::SpiSlSclk,
+    ],
+    20 => [
+        into_gpclk0: GpClk0 = PinMode::GpClk(0),
+        into_spi1_mosi: Spi1Mosi = PinMode::SpiMosi(1),
+        into_pcm_din: PcmDin = PinMode::PcmDin,
+        into_smi_sd12: SmiSd12 = PinMode::SmiSd(12),
+        into_spi_sl_miso: SpiSlMiso = PinMode::SpiSlMiso,
+    ],
+    21 => [
+        into_gpclk1: GpClk1 = PinMode::GpClk(1),
+        into_spi1_sclk: Spi1Sclk = PinMode::SpiSclk(1),
+        into_pcm_dout: PcmDout = PinMode::PcmDout,
+        into_smi_sd13: SmiSd13 = PinMode::SmiSd(13),
+        into_spi_sl_cs: SpiSlCs = PinMode::SpiSlCs,
+    ],
+    22 => [
+        into_smi_sd14: SmiSd14 = PinMode::SmiSd(14),
+        into_jtag_trst: JtagTrst = PinMode::JtagTrst,
+    ],
+    23 => [
+        into_smi_sd15: SmiSd15 = PinMode::SmiSd(15),
+        into_jtag_rtck: JtagRtck = PinMode::JtagRtck,
+    ],
+    24 => [
+        into_smi_sd16: SmiSd16 = PinMode::SmiSd(16),
+        into_jtag_tdo: JtagTdo = PinMode::JtagTdo,
+    ],
+    25 => [
+        into_smi_sd17: SmiSd17 = PinMode::SmiSd(17),
+        into_jtag_tck: JtagTck = PinMode::JtagTck,
+    ],
+    26 => [
+        into_jtag_tdi: JtagTdi = PinMode::JtagTdi,
+    ],
+    27 => [
+        into_jtag_tms: JtagTms = PinMode::JtagTms,
+    ],
+    28 => [
+        into_bsc0_sda: Bsc0Sda = PinMode::BscSda(0),
+        into_pcm_clk: PcmClk = PinMode::PcmClk,
+        into_smi_sa5: SmiSa5 = PinMode::SmiSa(5),
+    ],
+    29 => [
+        into_bsc0_scl: Bsc0Scl = PinMode::BscScl(0),
+        into_pcm_fs: PcmFs = PinMode::PcmFs,
+        into_smi_sa4: SmiSa4 = PinMode::SmiSa(4),
+    ],
+    30 => [
+        into_uart0_cts: Uart0Cts = PinMode::UartCts(0),
+        into_uart1_cts: Uart1Cts = PinMode::UartCts(1),
+        into_pcm_din: PcmDin = PinMode::PcmDin,
+        into_smi_sa3: SmiSa3 = PinMode::SmiSa(3),
+    ],
+    31 => [
+        into_uart0_rts: Uart0Rts = PinMode::UartRts(0),
+        into_uart1_rts: Uart1Rts = PinMode::UartRts(1),
+        into_pcm_dout: PcmDout = PinMode::PcmDout,
+        into_smi_sa2: SmiSa2 = PinMode::SmiSa(2),
+    ],
+    32 => [
+        into_gpclk0: GpClk0 = PinMode::GpClk(0),
+        into_uart0_txd: Uart0Txd = PinMode::UartTxd(0),
+        into_uart1_txd: Uart1Txd = PinMode::UartTxd(1),
+        into_smi_sa1: SmiSa1 = PinMode::SmiSa(1),
+    ],
+    33 => [
+        into_uart1_rxd: Uart1Rxd = PinMode::UartRxd(1),
+        into_uart0_rxd: Uart0Rxd = PinMode::UartRxd(0),
+        into_smi_sa0: SmiSa0 = PinMode::SmiSa(0),
+    ],
+    34 => [
+        into_gpclk0: GpClk0 = PinMode::GpClk(0),
+        into_smi_soe_n_se: SmiSoeNSe = PinMode::SmiSoeNSe,
+    ],
+    35 => [
+        into_spi0_cs1: Spi0Cs1 = PinMode::SpiCs(0, 1),
+        into_smi_swe_n_srw_n: SmiSweNSrwN = PinMode::SmiSweNSrwN,
+    ],
+    36 => [
+        into_spi0_cs0: Spi0Cs0 = PinMode::SpiCs(0, 0),
+        into_uart0_txd: Uart0Txd = PinMode::UartTxd(0),
+        into_smi_sd0: SmiSd0 = PinMode::SmiSd(0),
+    ],
+    37 => [
+        into_spi0_miso: Spi0Miso = PinMode::SpiMiso(0),
+        into_uart0_rxd: Uart0Rxd = PinMode::UartRxd(0),
+        into_smi_sd1: SmiSd1 = PinMode::SmiSd(1),
+    ],
+    38 => [
+        into_spi0_mosi: Spi0Mosi = PinMode::SpiMosi(0),
+        into_uart0_rts: Uart0Rts = PinMode::UartRts(0),
+        into_smi_sd2: SmiSd2 = PinMode::SmiSd(2),
+    ],
+    39 => [
+        into_spi0_sclk: Spi0Sclk = PinMode::SpiSclk(0),
+        into_uart0_cts: Uart0Cts = PinMode::UartCts(0),
+        into_smi_sd3: SmiSd3 = PinMode::SmiSd(3),
+    ],
+    40 => [
+        into_pwm0: Pwm0 = PinMode::Pwm(0),
+        into_uart1_txd: Uart1Txd = PinMode::UartTxd(1),
+        into_smi_sd4: SmiSd4 = PinMode::SmiSd(4),
+    ],
+    41 => [
+        into_pwm1: Pwm1 = PinMode::Pwm(1),
+        into_uart1_rxd: Uart1Rxd = PinMode::UartRxd(1),
+        into_smi_sd5: SmiSd5 = PinMode::SmiSd(5),
+    ],
+    42 => [
+        into_gpclk1: GpClk1 = PinMode::GpClk(1),
+        into_uart1_rts: Uart1Rts = PinMode::UartRts(1),
+        into_smi_sd6: SmiSd6 = PinMode::SmiSd(6),
+    ],
+    43 => [
+        into_gpclk2: GpClk2 = PinMode::GpClk(2),
+        into_uart1_cts: Uart1Cts = PinMode::UartCts(1),
+        into_smi_sd7: SmiSd7 = PinMode::SmiSd(7),
+    ],
+    44 => [
+        into_bsc0_sda: Bsc0Sda = PinMode::BscSda(0),
+        into_bsc1_sda: Bsc1Sda = PinMode::BscSda(1),
+        into_gpclk1: GpClk1 = PinMode::GpClk(1),
+        into_smi_sd8: SmiSd8 = PinMode::SmiSd(8),
+    ],
+    45 => [
+        into_bsc0_scl: Bsc0Scl = PinMode::BscScl(0),
+        into_bsc1_scl: Bsc1Scl = PinMode::BscScl(1),
+        into_pwm1: Pwm1 = PinMode::Pwm(1),
+        into_smi_sd9: SmiSd9 = PinMode::SmiSd(9),
+    ],
+}
+
+macro_rules! pins {
+    ($($field:ident: $pin:literal),* $(,)?) => {
+        // Every pin of the SoC, handed out once
+        pub struct Pins {
+            $(pub $field: Pin<$pin, mode::Unknown>,)*
+        }
+
+        impl Pins {
+            // None if they were already taken
+            pub fn take() -> Option<Pins> {
+                if TAKEN.swap(true, Ordering::AcqRel) {
+                    return None;
+                }
+                Some(Pins {
+                    $($field: Pin::new(),)*
+                })
+            }
+        }
+    };
+}
+
+pins! {
+    p0: 0, p1: 1, p2: 2, p3: 3, p4: 4, p5: 5,
+    p6: 6, p7: 7, p8: 8, p9: 9, p10: 10, p11: 11,
+    p12: 12, p13: 13, p14: 14, p15: 15, p16: 16, p17: 17,
+    p18: 18, p19: 19, p20: 20, p21: 21, p22: 22, p23: 23,
+    p24: 24, p25: 25, p26: 26, p27: 27, p28: 28, p29: 29,
+    p30: 30, p31: 31, p32: 32, p33: 33, p34: 34, p35: 35,
+    p36: 36, p37: 37, p38: 38, p39: 39, p40: 40, p41: 41,
+    p42: 42, p43: 43, p44: 44, p45: 45, p46: 46, p47: 47,
+    p48: 48, p49: 49, p50: 50, p51: 51, p52: 52, p53: 53
+}
+
+impl<const N: usize, MODE> digital::ErrorType for Pin<N, MODE> {
+    type Error = Errcode;
+}
+
+impl<const N: usize> Pin<N, mode::Output> {
+    pub fn set_high(&mut self) {
+        GPIO.write_level(N, true);
+    }
+
+    pub fn set_low(&mut self) {
+        GPIO.write_level(N, false);
+    }
+
+    pub fn is_set_high(&self) -> bool {
+        GPIO.driven_level(N)
+    }
+}
+
+impl<const N: usize> Pin<N, mode::Input> {
+    pub fn with_pull(self, pull: Pull) -> Pin<N, mode::Input> {
+        GPIO.write_pull(&[N], pull);
+        self
+    }
+
+    pub fn is_high(&self) -> bool {
+        GPIO.read_level(N)
+    }
+}
+
+impl<const N: usize> digital::OutputPin for Pin<N, mode::Output> {
+    fn set_low(&mut self) -> Result<(), Errcode> {
+        Pin::set_low(self);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Errcode> {
+        Pin::set_high(self);
+        Ok(())
+    }
+}
+
+impl<const N: usize> digital::StatefulOutputPin for Pin<N, mode::Output> {
+    fn is_set_high(&mut self) -> Result<bool, Errcode> {
+        Ok(Pin::is_set_high(self))
+    }
+
+    fn is_set_low(&mut self) -> Result<bool, Errcode> {
+        Ok(!Pin::is_set_high(self))
+    }
+}
+
+impl<const N: usize> digital::InputPin for Pin<N, mode::Input> {
+    fn is_high(&mut self) -> Result<bool, Errcode> {
+        Ok(Pin::is_high(self))
+    }
+
+    fn is_low(&mut self) -> Result<bool, Errcode> {
+        Ok(!Pin::is_high(self))
+    }
+}
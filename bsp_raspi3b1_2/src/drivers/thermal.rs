@@ -0,0 +1,93 @@
+use tock_registers::interfaces::{Readable, Writeable};
+use tock_registers::registers::{ReadOnly, ReadWrite};
+use tock_registers::{register_bitfields, register_structs};
+
+use crate::memory::{MMIODerefWrapper, THERMAL_BASE};
+use crate::sync::SpinLock;
+
+// Conversion of the ADC value for the BCM2837, in millidegrees Celsius
+//    Same coefficients as the Linux bcm2835_thermal driver.
+const OFFSET_MILLICELSIUS: i32 = 412_000;
+const SLOPE_MILLICELSIUS: i32 = -538;
+
+// Reset duration recommended for the sensor, in sensor clock cycles
+const RESET_DELAY: u32 = 0xFE;
+
+pub static THERMAL: ThermalDriver = ThermalDriver::init();
+
+// On-die temperature sensor of the SoC
+//    Unlike the mailbox, it can be read from anywhere, IRQ handlers included. Its clock is set up
+//    by the firmware.
+pub struct ThermalDriver {
+    registers: SpinLock<Registers>,
+}
+
+impl ThermalDriver {
+    const fn init() -> ThermalDriver {
+        ThermalDriver {
+            registers: SpinLock::new_irqsafe(Registers::new(THERMAL_BASE)),
+        }
+    }
+
+    // Start the sensor, unless the firmware already did
+    pub(crate) fn enable(&self) {
+        self.registers.lock(|reg| {
+            if reg.CTL.is_set(CTL::RSTB) {
+                return;
+            }
+            let config = CTL::CTRL.val(1) + CTL::REGULEN::SET + CTL::RSTDELAY.val(RESET_DELAY);
+            reg.CTL.write(config);
+            reg.CTL.write(config + CTL::RSTB::SET);
+        });
+    }
+
+    // Current temperature of the SoC, None until the sensor has a first conversion ready
+    pub fn temperature(&self) -> Option<i32> {
+        let stat = self.registers.lock(|reg| reg.STAT.extract());
+        if !stat.is_set(STAT::VALID) {
+            return None;
+        }
+        Some(OFFSET_MILLICELSIUS + SLOPE_MILLICELSIUS * stat.read(STAT::DATA) as i32)
+    }
+}
+
+register_bitfields! {
+    u32,
+
+    /// Sensor control
+    CTL [
+        /// Power down
+        PRWDW OFFSET(0) NUMBITS(1) [],
+        /// Reset, active low
+        RSTB OFFSET(1) NUMBITS(1) [],
+        /// Bandgap reference voltage
+        CTRL OFFSET(2) NUMBITS(3) [],
+        EN_INT OFFSET(5) NUMBITS(1) [],
+        DIRECT OFFSET(6) NUMBITS(1) [],
+        CLR_INT OFFSET(7) NUMBITS(1) [],
+        /// Interrupt threshold, as an ADC value
+        THOLD OFFSET(8) NUMBITS(10) [],
+        RSTDELAY OFFSET(18) NUMBITS(8) [],
+        /// Voltage regulator enable
+        REGULEN OFFSET(26) NUMBITS(1) [],
+    ],
+
+    /// Sensor status
+    STAT [
+        DATA OFFSET(0) NUMBITS(10) [],
+        VALID OFFSET(10) NUMBITS(1) [],
+        INTERRUPT OFFSET(11) NUMBITS(1) [],
+    ],
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => CTL: ReadWrite<u32, CTL::Register>),
+        (0x04 => STAT: ReadOnly<u32, STAT::Register>),
+        (0x08 => @END),
+    }
+}
+
+/// Abstraction for the associated MMIO registers.
+type Registers = MMIODerefWrapper<RegisterBlock>;
@@ -1,85 +1,207 @@
-// TODO   Make timer work
-//    - Make RwLock
-//    - Set timer resolution
-//    - Make Vec
-//    - Register new tries to find a free spot before creating a new one
-
 use aarch64_cpu::asm;
+use tock_registers::interfaces::{Readable, Writeable};
+use tock_registers::registers::{ReadOnly, ReadWrite};
+use tock_registers::{register_bitfields, register_structs};
+
+use crate::cpu;
+use crate::errors::Errcode;
+use crate::memory::{MMIODerefWrapper, SYSTIMER_BASE};
+use crate::sync::{RwLock, SpinLock};
 
-type Vec<T> = [T; 5];
-type RwLock<T> = crate::sync::NullLock<T>;
+use super::irq::{IRQ, IRQ_SYSTEM_TIMER_1};
 
+// Minimum delay between now and the next compare match we program
+//    The compare only fires on equality, a value already in the past would only match after the
+//    32 bits counter wrapped around (~71 minutes later).
 pub const TIMER_RESOLUTION_US: u64 = 30;
 pub const MAX_TIMERS_COUNT: usize = 10;
 
 pub static TIMER: TimerDriver = TimerDriver::init();
 
 // Do not use this struct for sampling or screen rendering, but everything else is fine
+//    Backed by the free-running 1 MHz system timer, compare channel 1 (channels 0 and 2 are used
+//    by the GPU). Each registered timer holds its deadline in system timer ticks (microseconds).
 pub struct TimerDriver {
-    // registered_timers: RwLock<Vec<u64>>,
-    // free: RwLock<Vec<bool>>,
+    registers: SpinLock<Registers>,
+    registered_timers: RwLock<[Option<u64>; MAX_TIMERS_COUNT]>,
+    irq_enabled: SpinLock<bool>,
 }
 
 impl TimerDriver {
     const fn init() -> TimerDriver {
         TimerDriver {
-            // registered_timers: RwLock::new(Vec::new()),
-            // free: RwLock::new(Vec::new()),
+            registers: SpinLock::new_irqsafe(Registers::new(SYSTIMER_BASE)),
+            registered_timers: RwLock::new_irqsafe([None; MAX_TIMERS_COUNT]),
+            irq_enabled: SpinLock::new(false),
         }
     }
 
+    // Route the compare channel IRQ to `tick`, until then `wait` has to spin
+    pub(crate) fn enable_irq(&self) -> Result<(), Errcode> {
+        self.registers.lock(|reg| reg.CS.write(CS::M1::SET));
+        IRQ.register_irq(IRQ_SYSTEM_TIMER_1, || TIMER.tick())?;
+        IRQ.enable_irq(IRQ_SYSTEM_TIMER_1)?;
+        self.irq_enabled.lock(|e| *e = true);
+        self.schedule_next();
+        Ok(())
+    }
+
+    // Current value of the free-running counter, in microseconds since boot
+    pub fn now(&self) -> u64 {
+        self.registers.lock(|reg| loop {
+            let hi = reg.CHI.get();
+            let lo = reg.CLO.get();
+            // The low word wrapped between the two reads, try again
+            if reg.CHI.get() == hi {
+                break (u64::from(hi) << 32) | u64::from(lo);
+            }
+        })
+    }
+
     // Called inside the Timer IRQ handler
     pub(crate) fn tick(&self) {
-        // for (idx, is_free) in self.free.read().iter() {
-        //    if !is_free {
-        //         let count = self.registered_timers.write().get_mut(&idx);
-        //         *count = count.saturating_sub(TIMER_RESOLUTION_US);
-        //     }
-        // }
-        todo!();
+        self.registers.lock(|reg| reg.CS.write(CS::M1::SET));
+        self.schedule_next();
     }
 
-    pub fn free(&self, idx: usize) {
-        // if self.free.read().get(&idx) {
-        //     panic!("Double free on timer {idx}");
-        // }
-        // *self.free.write().get_mut(&idx) = true;
-        todo!();
+    // Program the compare channel for the closest deadline still in the future
+    fn schedule_next(&self) {
+        let now = self.now();
+        let next = self.registered_timers.read(|timers| {
+            timers
+                .iter()
+                .flatten()
+                .filter(|deadline| **deadline > now)
+                .min()
+                .copied()
+        });
+        let Some(deadline) = next else { return };
+        let mut target = deadline.max(now + TIMER_RESOLUTION_US);
+        loop {
+            self.registers.lock(|reg| reg.C1.set(target as u32));
+            // An IRQ between `now` and the write may have made us late, the compare would then
+            //    only match after the wrap around: fire as soon as possible instead
+            let now = self.now();
+            if now < target {
+                break;
+            }
+            target = now + TIMER_RESOLUTION_US;
+        }
+    }
+
+    pub fn free(&self, idx: usize) -> Result<(), Errcode> {
+        check_timer(idx)?;
+        self.registered_timers
+            .write(|timers| match timers[idx].take() {
+                Some(_) => Ok(()),
+                None => Err(Errcode::TimerNotRegistered(idx)),
+            })
     }
 
     // Register new timer to follow, return the number
-    pub fn register_new(&self, time_us: u64) -> usize {
-        // let idx = self.registered_timers.read().len();
-        // assert!((idx + 1) < MAX_TIMERS_COUNT, "Max timers count reached");
-        // self.registered_timers.write().push(time_us);
-        // self.free.write().push(false);
-        // idx
-        todo!();
+    pub fn register_new(&self, time_us: u64) -> Result<usize, Errcode> {
+        let deadline = self.now().saturating_add(time_us);
+        let idx = self.registered_timers.write(|timers| {
+            let idx = timers
+                .iter()
+                .position(|t| t.is_none())
+                .ok_or(Errcode::TimerOutOfTimers)?;
+            timers[idx] = Some(deadline);
+            Ok(idx)
+        })?;
+        self.schedule_next();
+        Ok(idx)
     }
 
-    pub fn set(&self, timer_nb: usize, time_us: u64) {
-        // *self.free.write().get_mut(&timer_nb) = false;
-        // match self.registered_timers.write().get_mut(&timer_nb) {
-        //   Some(timer) => *timer = time_us,
-        //   None => panic!("Attempt to set timer {timer_nb}, but it doesn't exist"),
-        // }
-        todo!();
+    pub fn set(&self, timer_nb: usize, time_us: u64) -> Result<(), Errcode> {
+        check_timer(timer_nb)?;
+        let deadline = self.now().saturating_add(time_us);
+        self.registered_timers
+            .write(|timers| match timers[timer_nb].as_mut() {
+                Some(timer) => {
+                    *timer = deadline;
+                    Ok(())
+                }
+                None => Err(Errcode::TimerNotRegistered(timer_nb)),
+            })?;
+        self.schedule_next();
+        Ok(())
     }
 
     // Get remaining time to wait (in microseconds)
-    pub fn get(&self, timer_nb: usize) -> u64 {
-        // if let Some(rest) = self.registered_timers.read().get(timer_nb) {
-        //   rest
-        // } else {
-        //   panic!("Unable to get timer {timer_nb}: Not there");
-        // }
-        todo!();
+    pub fn get(&self, timer_nb: usize) -> Result<u64, Errcode> {
+        check_timer(timer_nb)?;
+        let now = self.now();
+        match self.registered_timers.read(|timers| timers[timer_nb]) {
+            Some(deadline) => Ok(deadline.saturating_sub(now)),
+            None => Err(Errcode::TimerNotRegistered(timer_nb)),
+        }
+    }
+
+    pub fn wait(&self, time_us: u64) -> Result<(), Errcode> {
+        let nb = self.register_new(time_us)?;
+        // The slot is given back whatever happened
+        let res = self.wait_for(nb);
+        let freed = self.free(nb);
+        res.and(freed)
     }
 
-    pub fn wait(&self, time_us: u64) {
-        let nb = self.register_new(time_us);
-        while self.get(nb) > 0 {
-            asm::wfi();
+    fn wait_for(&self, nb: usize) -> Result<(), Errcode> {
+        let sleep = self.irq_enabled.lock(|e| *e);
+        loop {
+            // IRQs masked between the check and WFI, else the deadline could be reached in
+            //    between and we would sleep until an unrelated IRQ. WFI still wakes up on a
+            //    pending masked IRQ.
+            let daif = cpu::irq_save();
+            let left = self.get(nb);
+            if sleep && matches!(left, Ok(1..)) {
+                asm::wfi();
+            }
+            cpu::irq_restore(daif);
+            if left? == 0 {
+                break;
+            }
+            if !sleep {
+                asm::nop();
+            }
         }
+        Ok(())
+    }
+}
+
+fn check_timer(idx: usize) -> Result<(), Errcode> {
+    if idx < MAX_TIMERS_COUNT {
+        Ok(())
+    } else {
+        Err(Errcode::TimerInvalid(idx))
     }
 }
+
+register_bitfields! {
+    u32,
+
+    /// System Timer Control / Status, write 1 to clear a match
+    CS [
+        M0 OFFSET(0) NUMBITS(1) [],
+        M1 OFFSET(1) NUMBITS(1) [],
+        M2 OFFSET(2) NUMBITS(1) [],
+        M3 OFFSET(3) NUMBITS(1) [],
+    ],
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => CS: ReadWrite<u32, CS::Register>),
+        (0x04 => CLO: ReadOnly<u32>),
+        (0x08 => CHI: ReadOnly<u32>),
+        (0x0C => C0: ReadWrite<u32>),
+        (0x10 => C1: ReadWrite<u32>),
+        (0x14 => C2: ReadWrite<u32>),
+        (0x18 => C3: ReadWrite<u32>),
+        (0x1C => @END),
+    }
+}
+
+/// Abstraction for the associated MMIO registers.
+type Registers = MMIODerefWrapper<RegisterBlock>;
@@ -6,24 +6,32 @@ use tock_registers::{
 };
 
 use crate::{
+    clocks::{self, Clock},
+    errors::Errcode,
     memory::{MMIODerefWrapper, UART0_BASE},
-    sync::NullLock,
+    sync::SpinLock,
 };
 
-use super::gpio::PinMode;
+use super::pins::{mode, Pin};
 
 pub static UART: UartDriver = UartDriver::init();
 
+// Speed expected by the chainloader server
+pub const UART_BAUD_RATE: u32 = 921_600;
+
+// What `init_uart_clock` is set to in our config.txt, used if the firmware cannot be asked
+const DEFAULT_UART_CLOCK_HZ: u32 = 48_000_000;
+
 pub struct UartDriver {
-    registers: NullLock<Registers>,
-    pub init: NullLock<bool>,
+    registers: SpinLock<Registers>,
+    pub init: SpinLock<bool>,
 }
 
 impl UartDriver {
     const fn init() -> UartDriver {
         UartDriver {
-            registers: NullLock::new(Registers::new(UART0_BASE)),
-            init: NullLock::new(false),
+            registers: SpinLock::new_irqsafe(Registers::new(UART0_BASE)),
+            init: SpinLock::new(false),
         }
     }
 
@@ -39,18 +47,31 @@ impl UartDriver {
         }
     }
 
-    pub fn configure(&self, txd: usize, rxd: usize) {
-        let gpios = &super::GPIO;
-        gpios.configure(&[(txd, PinMode::UartTxd(0)), (rxd, PinMode::UartRxd(0))]);
-        gpios.disable_pud(&[txd, rxd]);
+    // Only pins set to the UART0 functions are accepted
+    //    The UART keeps them for as long as the kernel runs.
+    pub fn configure_pins<const TXD: usize, const RXD: usize>(
+        &self,
+        txd: Pin<TXD, mode::Uart0Txd>,
+        rxd: Pin<RXD, mode::Uart0Rxd>,
+    ) -> Result<(), Errcode> {
+        self.setup(txd.number(), rxd.number())
+    }
+
+    // Pins already set to the UART functions
+    fn setup(&self, txd: usize, rxd: usize) -> Result<(), Errcode> {
+        super::GPIO.disable_pud(&[txd, rxd])?;
+        let (ibrd, fbrd) = baud_divisors(
+            clocks::rate(Clock::Uart).unwrap_or(DEFAULT_UART_CLOCK_HZ),
+            UART_BAUD_RATE,
+        );
         self.flush();
         self.registers.lock(|reg| {
             reg.CR.set(0); // Turn the UART off temporarily.
             reg.ICR.write(ICR::ALL::CLEAR); // Clear all pending interrupts.
 
             // Set the baud rate, 8N1 and FIFO enabled.
-            reg.IBRD.write(IBRD::BAUD_DIVINT.val(3));
-            reg.FBRD.write(FBRD::BAUD_DIVFRAC.val(16));
+            reg.IBRD.write(IBRD::BAUD_DIVINT.val(ibrd));
+            reg.FBRD.write(FBRD::BAUD_DIVFRAC.val(fbrd));
             reg.LCR_H
                 .write(LCR_H::WLEN::EightBit + LCR_H::FEN::FifosEnabled);
 
@@ -59,6 +80,7 @@ impl UartDriver {
                 .write(CR::UARTEN::Enabled + CR::TXE::Enabled + CR::RXE::Enabled);
         });
         self.init.lock(|i| *i = true);
+        Ok(())
     }
 
     pub fn write(&self, s: &str) {
@@ -94,6 +116,21 @@ impl UartDriver {
     }
 }
 
+// Integer and fractional parts of UARTCLK / (16 * baud rate), the fraction in 64ths
+//    e.g. 48 MHz at 921600 bauds: 3.2552 -> IBRD 3, FBRD 16
+fn baud_divisors(clock_hz: u32, baud_rate: u32) -> (u32, u32) {
+    let baud_rate = u64::from(baud_rate);
+    // Divisor in 64ths, rounded to the nearest
+    let divisor = (u64::from(clock_hz) * 4 + baud_rate / 2) / baud_rate;
+    let ibrd = (divisor >> 6).clamp(1, 0xFFFF) as u32;
+    let fbrd = if ibrd == 0xFFFF {
+        0
+    } else {
+        (divisor & 0x3F) as u32
+    };
+    (ibrd, fbrd)
+}
+
 register_bitfields! {
     u32,
 
@@ -1,12 +1,155 @@
+use core::fmt;
 use core::panic::PanicInfo;
 
+use crate::allocator::heap_stats;
 use crate::cpu::wait_forever;
+use crate::drivers::gpio::PinMode;
 use crate::println;
+use crate::sync::bypass_locks;
 
 #[derive(Debug)]
-pub enum Errcode {}
+pub enum Errcode {
+    // GPIO, by pin number
+    GpioInvalidPin(usize),
+    GpioInvalidPinFunction { pin: usize, mode: PinMode },
+    GpioPinConflict(usize),
+    GpioPinOwned { pin: usize, owner: &'static str },
+    GpioPinNotOwned { pin: usize, owner: &'static str },
+    GpioPinHasHandle(usize),
+
+    IrqInvalid(usize),
+
+    // System timer, by timer number
+    TimerInvalid(usize),
+    TimerNotRegistered(usize),
+    TimerOutOfTimers,
+
+    // Name of the device used before its driver was set up
+    DeviceNotInitialized(&'static str),
+
+    // Chainloading
+    ChainloadMmuEnabled,
+    ChainloadImageTooLarge(u32),
+    ChainloadUartRead,
+
+    // Mailbox property interface
+    MailboxRequestTooLarge,
+    MailboxTimeout,
+    MailboxRequestFailed,
+    MailboxTagNotAnswered(u32),
+    MailboxUnsupportedTag(u32),
+    MailboxInvalidReply(u32),
+
+    // Revision code of the board we refuse to run on
+    UnsupportedBoard(u32),
+
+    // Id of a clock the firmware does not know
+    ClockNotFound(u32),
+
+    // Power domains, by device id
+    PowerDeviceNotFound(u32),
+    PowerStateNotChanged(u32),
+    PowerNotAcquired(u32),
+
+    // Framebuffer
+    ScreenUnsupportedDepth(u32),
+    ScreenAllocationFailed,
+    FontInvalid,
+
+    // Console
+    ConsoleTooManySinks,
+    ConsoleInvalidSink,
+    ConsoleTooManyCommands,
+    ConsoleCommandExists,
+    ConsoleUnknownCommand,
+
+    // Sensors monitoring
+    MonitorTooManyWatches,
+    MonitorInvalidWatch,
+
+    // Buttons and rotary encoders
+    InputTooManyButtons,
+    InputTooManyEncoders,
+    InputQueueFull,
+}
+
+// Nothing more precise to say in the embedded-hal terms
+impl embedded_hal::digital::Error for Errcode {
+    fn kind(&self) -> embedded_hal::digital::ErrorKind {
+        embedded_hal::digital::ErrorKind::Other
+    }
+}
+
+impl fmt::Display for Errcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Errcode::GpioInvalidPin(pin) => write!(f, "GPIO {pin} does not exist"),
+            Errcode::GpioInvalidPinFunction { pin, mode } => {
+                write!(f, "GPIO {pin} cannot be used as {mode:?}")
+            }
+            Errcode::GpioPinConflict(pin) => {
+                write!(f, "GPIO {pin} configured twice in the same request")
+            }
+            Errcode::GpioPinOwned { pin, owner } => write!(f, "GPIO {pin} is used by {owner}"),
+            Errcode::GpioPinNotOwned { pin, owner } => {
+                write!(f, "GPIO {pin} is not used by {owner}")
+            }
+            Errcode::GpioPinHasHandle(pin) => write!(f, "GPIO {pin} is held by a pin handle"),
+            Errcode::IrqInvalid(nb) => write!(f, "IRQ {nb} does not exist"),
+            Errcode::TimerInvalid(nb) => write!(f, "timer {nb} does not exist"),
+            Errcode::TimerNotRegistered(nb) => write!(f, "timer {nb} is not registered"),
+            Errcode::TimerOutOfTimers => write!(f, "all the timers are in use"),
+            Errcode::DeviceNotInitialized(device) => write!(f, "{device} not initialized"),
+            Errcode::ChainloadMmuEnabled => write!(f, "cannot chainload with the MMU enabled"),
+            Errcode::ChainloadUartRead => write!(f, "nothing to read from the UART"),
+            Errcode::ChainloadImageTooLarge(size) => {
+                write!(f, "kernel image too large ({size} bytes)")
+            }
+            Errcode::MailboxRequestTooLarge => write!(f, "mailbox request too large"),
+            Errcode::MailboxTimeout => write!(f, "mailbox timed out"),
+            Errcode::MailboxRequestFailed => write!(f, "mailbox request failed"),
+            Errcode::MailboxTagNotAnswered(tag) => {
+                write!(f, "mailbox tag {tag:#x} not answered by the firmware")
+            }
+            Errcode::MailboxUnsupportedTag(tag) => write!(f, "mailbox tag {tag:#x} not supported"),
+            Errcode::MailboxInvalidReply(tag) => {
+                write!(f, "invalid reply to the mailbox tag {tag:#x}")
+            }
+            Errcode::UnsupportedBoard(revision) => {
+                write!(f, "unsupported board (revision {revision:#x})")
+            }
+            Errcode::ClockNotFound(id) => write!(f, "clock {id} does not exist"),
+            Errcode::PowerDeviceNotFound(id) => write!(f, "power domain {id} does not exist"),
+            Errcode::PowerStateNotChanged(id) => {
+                write!(f, "the firmware did not switch power domain {id}")
+            }
+            Errcode::PowerNotAcquired(id) => write!(f, "power domain {id} released too many times"),
+            Errcode::ScreenUnsupportedDepth(depth) => {
+                write!(f, "unsupported screen depth ({depth} bpp)")
+            }
+            Errcode::ScreenAllocationFailed => write!(f, "framebuffer allocation failed"),
+            Errcode::FontInvalid => write!(f, "invalid PSF font"),
+            Errcode::ConsoleTooManySinks => write!(f, "too many console sinks"),
+            Errcode::ConsoleInvalidSink => write!(f, "no such console sink"),
+            Errcode::ConsoleTooManyCommands => write!(f, "too many console commands"),
+            Errcode::ConsoleCommandExists => write!(f, "console command already registered"),
+            Errcode::ConsoleUnknownCommand => write!(f, "unknown command"),
+            Errcode::MonitorTooManyWatches => write!(f, "too many sensor watches"),
+            Errcode::MonitorInvalidWatch => write!(f, "no such sensor watch"),
+            Errcode::InputTooManyButtons => write!(f, "too many buttons"),
+            Errcode::InputTooManyEncoders => write!(f, "too many rotary encoders"),
+            Errcode::InputQueueFull => write!(f, "input event queue full, events lost"),
+        }
+    }
+}
 
 pub fn handle_panic(info: &PanicInfo) -> ! {
+    // Allocation failures end up here as well, through the default alloc error handler
+    bypass_locks();
     println!("Kernel panic ! {info}");
+    let stats = heap_stats();
+    if stats.size > 0 {
+        println!("{stats}");
+    }
     wait_forever();
 }
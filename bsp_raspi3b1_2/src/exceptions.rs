@@ -0,0 +1,347 @@
+// Rust side of the exception vector table defined in boot.rs
+//    Every entry of the table saves an `ExceptionContext` on the stack, then calls one of the
+//    handlers below with a pointer to it. The context is restored on return, so handlers can
+//    modify it (for example to skip the faulting instruction).
+
+use core::fmt;
+
+use aarch64_cpu::registers::{ESR_EL1, FAR_EL1};
+use tock_registers::interfaces::Readable;
+use tock_registers::registers::InMemoryRegister;
+
+use crate::println;
+
+/// Register frame saved by the vector table before calling a handler.
+#[repr(C)]
+pub struct ExceptionContext {
+    /// General purpose registers x0 to x29
+    pub gpr: [u64; 30],
+
+    /// Link register, aka x30
+    pub lr: u64,
+
+    /// Exception link register, the address to return to
+    pub elr_el1: u64,
+
+    /// Saved program status
+    pub spsr_el1: u64,
+
+    /// Exception syndrome register
+    pub esr_el1: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ExceptionOrigin {
+    CurrentElSp0,
+    CurrentElSpx,
+    LowerElAarch64,
+    LowerElAarch32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ExceptionKind {
+    Synchronous,
+    Irq,
+    Fiq,
+    SError,
+}
+
+impl fmt::Display for ExceptionContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "ELR_EL1:  {:#018x}", self.elr_el1)?;
+        writeln!(f, "SPSR_EL1: {:#018x}", self.spsr_el1)?;
+        for (i, reg) in self.gpr.iter().enumerate() {
+            write!(f, "x{i:<2}: {reg:#018x}")?;
+            if i % 2 == 1 {
+                writeln!(f)?;
+            } else {
+                write!(f, "    ")?;
+            }
+        }
+        write!(f, "lr : {:#018x}", self.lr)
+    }
+}
+
+/// Decoded view of a saved `ESR_EL1` value.
+pub struct ExceptionSyndrome(InMemoryRegister<u64, ESR_EL1::Register>);
+
+impl ExceptionSyndrome {
+    pub fn new(esr_el1: u64) -> ExceptionSyndrome {
+        ExceptionSyndrome(InMemoryRegister::new(esr_el1))
+    }
+
+    pub fn exception_class(&self) -> u64 {
+        self.0.read(ESR_EL1::EC)
+    }
+
+    pub fn iss(&self) -> u64 {
+        self.0.read(ESR_EL1::ISS)
+    }
+
+    pub fn is_data_abort(&self) -> bool {
+        matches!(
+            self.0.read_as_enum(ESR_EL1::EC),
+            Some(ESR_EL1::EC::Value::DataAbortCurrentEL)
+                | Some(ESR_EL1::EC::Value::DataAbortLowerEL)
+        )
+    }
+
+    pub fn is_instruction_abort(&self) -> bool {
+        matches!(
+            self.0.read_as_enum(ESR_EL1::EC),
+            Some(ESR_EL1::EC::Value::InstrAbortCurrentEL)
+                | Some(ESR_EL1::EC::Value::InstrAbortLowerEL)
+        )
+    }
+
+    // FAR_EL1 only holds something meaningful for aborts, alignment faults and watchpoints
+    pub fn far_valid(&self) -> bool {
+        use ESR_EL1::EC::Value::*;
+        if self.is_data_abort() || self.is_instruction_abort() {
+            return (self.iss() & ISS_ABORT_FNV) == 0;
+        }
+        matches!(
+            self.0.read_as_enum(ESR_EL1::EC),
+            Some(PCAlignmentFault) | Some(WatchpointCurrentEL) | Some(WatchpointLowerEL)
+        )
+    }
+
+    pub fn class_name(&self) -> &'static str {
+        use ESR_EL1::EC::Value::*;
+        match self.0.read_as_enum(ESR_EL1::EC) {
+            Some(Unknown) => "Unknown reason",
+            Some(TrappedWFIorWFE) => "Trapped WFI or WFE",
+            Some(TrappedMCRorMRC) | Some(TrappedMCRorMRC2) => "Trapped MCR or MRC (AArch32)",
+            Some(TrappedMCRRorMRRC) | Some(TrappedMRRC) => "Trapped MCRR or MRRC (AArch32)",
+            Some(TrappedLDCorSTC) => "Trapped LDC or STC (AArch32)",
+            Some(TrappedFP) => "Trapped SIMD / floating point access",
+            Some(BranchTarget) => "Branch target exception",
+            Some(IllegalExecutionState) => "Illegal execution state",
+            Some(SVC32) => "SVC instruction (AArch32)",
+            Some(SVC64) => "SVC instruction",
+            Some(HVC64) => "HVC instruction",
+            Some(SMC64) => "SMC instruction",
+            Some(TrappedMsrMrs) => "Trapped MSR, MRS or system instruction",
+            Some(TrappedSve) => "Trapped SVE access",
+            Some(PointerAuth) => "Pointer authentication failure",
+            Some(InstrAbortLowerEL) => "Instruction abort from lower EL",
+            Some(InstrAbortCurrentEL) => "Instruction abort from current EL",
+            Some(PCAlignmentFault) => "PC alignment fault",
+            Some(DataAbortLowerEL) => "Data abort from lower EL",
+            Some(DataAbortCurrentEL) => "Data abort from current EL",
+            Some(SPAlignmentFault) => "SP alignment fault",
+            Some(TrappedFP32) | Some(TrappedFP64) => "Trapped floating point exception",
+            Some(SError) => "SError interrupt",
+            Some(BreakpointLowerEL) | Some(BreakpointCurrentEL) => "Hardware breakpoint",
+            Some(SoftwareStepLowerEL) | Some(SoftwareStepCurrentEL) => "Software step",
+            Some(WatchpointLowerEL) | Some(WatchpointCurrentEL) => "Watchpoint",
+            Some(Bkpt32) => "BKPT instruction (AArch32)",
+            Some(Brk64) => "BRK instruction",
+            None => "Reserved exception class",
+        }
+    }
+}
+
+// Instruction / data abort ISS fields
+const ISS_ABORT_FNV: u64 = 1 << 10;
+const ISS_ABORT_EA: u64 = 1 << 9;
+const ISS_ABORT_S1PTW: u64 = 1 << 7;
+const ISS_DABORT_ISV: u64 = 1 << 24;
+const ISS_DABORT_WNR: u64 = 1 << 6;
+const ISS_ABORT_FSC_MASK: u64 = 0b11_1111;
+
+// Fault status code, common to instruction (IFSC) and data (DFSC) aborts
+fn fault_status_name(fsc: u64) -> &'static str {
+    match fsc {
+        0b00_0000..=0b00_0011 => "Address size fault",
+        0b00_0100..=0b00_0111 => "Translation fault",
+        0b00_1000..=0b00_1011 => "Access flag fault",
+        0b00_1100..=0b00_1111 => "Permission fault",
+        0b01_0000 => "Synchronous external abort",
+        0b01_0001 => "Synchronous tag check fault",
+        0b01_0100..=0b01_0111 => "Synchronous external abort on translation table walk",
+        0b01_1000 => "Synchronous parity or ECC error",
+        0b01_1100..=0b01_1111 => "Synchronous parity or ECC error on translation table walk",
+        0b10_0001 => "Alignment fault",
+        0b11_0000 => "TLB conflict abort",
+        0b11_0001 => "Unsupported atomic hardware update fault",
+        _ => "Unknown fault status",
+    }
+}
+
+impl fmt::Display for ExceptionSyndrome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "ESR_EL1:  {:#018x}", self.0.get())?;
+        writeln!(
+            f,
+            "    Exception class: {:#04x} - {}",
+            self.exception_class(),
+            self.class_name()
+        )?;
+        let il = if self.0.is_set(ESR_EL1::IL) { 32 } else { 16 };
+        writeln!(f, "    Instruction length: {il} bits")?;
+        write!(f, "    ISS: {:#09x}", self.iss())?;
+
+        if !(self.is_data_abort() || self.is_instruction_abort()) {
+            return Ok(());
+        }
+
+        let iss = self.iss();
+        let fsc = iss & ISS_ABORT_FSC_MASK;
+        writeln!(f)?;
+        write!(
+            f,
+            "    Fault status: {fsc:#04x} - {}",
+            fault_status_name(fsc)
+        )?;
+        if (0b00_0000..=0b00_1111).contains(&fsc) || (0b01_0100..=0b01_0111).contains(&fsc) {
+            write!(f, " (level {})", fsc & 0b11)?;
+        }
+        if self.is_data_abort() {
+            writeln!(f)?;
+            let access = if (iss & ISS_DABORT_WNR) != 0 {
+                "write"
+            } else {
+                "read"
+            };
+            write!(f, "    Access: {access}")?;
+            if (iss & ISS_DABORT_ISV) != 0 {
+                let size = 1 << ((iss >> 22) & 0b11);
+                let reg = (iss >> 16) & 0b1_1111;
+                write!(f, " of {size} bytes, register x{reg}")?;
+            }
+        }
+        if (iss & ISS_ABORT_S1PTW) != 0 {
+            writeln!(f)?;
+            write!(
+                f,
+                "    Fault on stage 2 translation of a stage 1 table walk"
+            )?;
+        }
+        if (iss & ISS_ABORT_EA) != 0 {
+            writeln!(f)?;
+            write!(f, "    External abort")?;
+        }
+        Ok(())
+    }
+}
+
+// Print everything we know about the exception on the console, then hand over to the panic path
+fn synchronous_exception_handler(origin: ExceptionOrigin, ctx: &ExceptionContext) -> ! {
+    let esr = ExceptionSyndrome::new(ctx.esr_el1);
+    println!();
+    println!("Synchronous exception from {origin:?}");
+    println!("{esr}");
+    if esr.far_valid() {
+        println!("FAR_EL1:  {:#018x}", FAR_EL1.get());
+    } else {
+        println!("FAR_EL1:  not valid");
+    }
+    println!("{ctx}");
+    panic!(
+        "Unrecoverable synchronous exception at {:#x}: {}",
+        ctx.elr_el1,
+        esr.class_name()
+    );
+}
+
+fn default_exception_handler(
+    origin: ExceptionOrigin,
+    kind: ExceptionKind,
+    ctx: &ExceptionContext,
+) -> ! {
+    panic!(
+        "Unhandled {kind:?} exception from {origin:?}\nESR_EL1:  {:#018x}\n{ctx}",
+        ctx.esr_el1
+    );
+}
+
+// Current exception level with SP_EL0
+//    We never use SP_EL0 while in EL1, so none of these should happen
+
+#[no_mangle]
+extern "C" fn current_el0_synchronous(ctx: &mut ExceptionContext) {
+    synchronous_exception_handler(ExceptionOrigin::CurrentElSp0, ctx);
+}
+
+#[no_mangle]
+extern "C" fn current_el0_irq(ctx: &mut ExceptionContext) {
+    default_exception_handler(ExceptionOrigin::CurrentElSp0, ExceptionKind::Irq, ctx);
+}
+
+#[no_mangle]
+extern "C" fn current_el0_fiq(ctx: &mut ExceptionContext) {
+    default_exception_handler(ExceptionOrigin::CurrentElSp0, ExceptionKind::Fiq, ctx);
+}
+
+#[no_mangle]
+extern "C" fn current_el0_serror(ctx: &mut ExceptionContext) {
+    default_exception_handler(ExceptionOrigin::CurrentElSp0, ExceptionKind::SError, ctx);
+}
+
+// Current exception level with SP_ELx
+//    This is where the kernel exceptions land
+
+#[no_mangle]
+extern "C" fn current_elx_synchronous(ctx: &mut ExceptionContext) {
+    synchronous_exception_handler(ExceptionOrigin::CurrentElSpx, ctx);
+}
+
+#[no_mangle]
+extern "C" fn current_elx_irq(_ctx: &mut ExceptionContext) {
+    crate::drivers::IRQ.handle_irq();
+}
+
+#[no_mangle]
+extern "C" fn current_elx_fiq(ctx: &mut ExceptionContext) {
+    default_exception_handler(ExceptionOrigin::CurrentElSpx, ExceptionKind::Fiq, ctx);
+}
+
+#[no_mangle]
+extern "C" fn current_elx_serror(ctx: &mut ExceptionContext) {
+    default_exception_handler(ExceptionOrigin::CurrentElSpx, ExceptionKind::SError, ctx);
+}
+
+// Lower exception level, AArch64
+
+#[no_mangle]
+extern "C" fn lower_aarch64_synchronous(ctx: &mut ExceptionContext) {
+    synchronous_exception_handler(ExceptionOrigin::LowerElAarch64, ctx);
+}
+
+#[no_mangle]
+extern "C" fn lower_aarch64_irq(ctx: &mut ExceptionContext) {
+    default_exception_handler(ExceptionOrigin::LowerElAarch64, ExceptionKind::Irq, ctx);
+}
+
+#[no_mangle]
+extern "C" fn lower_aarch64_fiq(ctx: &mut ExceptionContext) {
+    default_exception_handler(ExceptionOrigin::LowerElAarch64, ExceptionKind::Fiq, ctx);
+}
+
+#[no_mangle]
+extern "C" fn lower_aarch64_serror(ctx: &mut ExceptionContext) {
+    default_exception_handler(ExceptionOrigin::LowerElAarch64, ExceptionKind::SError, ctx);
+}
+
+// Lower exception level, AArch32
+
+#[no_mangle]
+extern "C" fn lower_aarch32_synchronous(ctx: &mut ExceptionContext) {
+    synchronous_exception_handler(ExceptionOrigin::LowerElAarch32, ctx);
+}
+
+#[no_mangle]
+extern "C" fn lower_aarch32_irq(ctx: &mut ExceptionContext) {
+    default_exception_handler(ExceptionOrigin::LowerElAarch32, ExceptionKind::Irq, ctx);
+}
+
+#[no_mangle]
+extern "C" fn lower_aarch32_fiq(ctx: &mut ExceptionContext) {
+    default_exception_handler(ExceptionOrigin::LowerElAarch32, ExceptionKind::Fiq, ctx);
+}
+
+#[no_mangle]
+extern "C" fn lower_aarch32_serror(ctx: &mut ExceptionContext) {
+    default_exception_handler(ExceptionOrigin::LowerElAarch32, ExceptionKind::SError, ctx);
+}
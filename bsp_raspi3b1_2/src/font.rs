@@ -0,0 +1,129 @@
+use crate::errors::Errcode;
+use crate::sync::Lazy;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_HEADER_SIZE: usize = 4;
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+const PSF2_HEADER_SIZE: usize = 32;
+
+// Drawn for the characters the font does not have
+const REPLACEMENT_CHAR: char = '?';
+
+static DEFAULT_FONT_DATA: &[u8] = include_bytes!("fonts/mono-8x16.psf");
+
+static DEFAULT_FONT: Lazy<Font> =
+    Lazy::new(|| Font::from_psf(DEFAULT_FONT_DATA).expect("Invalid embedded font"));
+
+/// Monospace 8x16 font embedded in the kernel, ASCII only.
+pub fn default_font() -> &'static Font {
+    &DEFAULT_FONT
+}
+
+// Bitmap font in the PC Screen Font format (version 1 or 2)
+//    Glyphs are indexed by their code point, no unicode translation table is used.
+pub struct Font {
+    width: usize,
+    height: usize,
+    bytes_per_row: usize,
+    bytes_per_glyph: usize,
+    nb_glyphs: usize,
+    glyphs: &'static [u8],
+}
+
+impl Font {
+    pub fn from_psf(data: &'static [u8]) -> Result<Font, Errcode> {
+        if data.starts_with(&PSF2_MAGIC) {
+            Font::from_psf2(data)
+        } else if data.starts_with(&PSF1_MAGIC) {
+            Font::from_psf1(data)
+        } else {
+            Err(Errcode::FontInvalid)
+        }
+    }
+
+    fn from_psf1(data: &'static [u8]) -> Result<Font, Errcode> {
+        let mode = *data.get(2).ok_or(Errcode::FontInvalid)?;
+        let height = usize::from(*data.get(3).ok_or(Errcode::FontInvalid)?);
+        let nb_glyphs = if (mode & PSF1_MODE_512) != 0 {
+            512
+        } else {
+            256
+        };
+        Font::new(data, PSF1_HEADER_SIZE, 8, height, height, nb_glyphs)
+    }
+
+    fn from_psf2(data: &'static [u8]) -> Result<Font, Errcode> {
+        let field = |index: usize| -> Result<usize, Errcode> {
+            let bytes = data
+                .get(index * 4..index * 4 + 4)
+                .ok_or(Errcode::FontInvalid)?;
+            Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+        };
+        // magic, version, header size, flags, length, glyph size, height, width
+        let header_size = field(2)?;
+        if header_size < PSF2_HEADER_SIZE {
+            return Err(Errcode::FontInvalid);
+        }
+        Font::new(
+            data,
+            header_size,
+            field(7)?,
+            field(6)?,
+            field(5)?,
+            field(4)?,
+        )
+    }
+
+    fn new(
+        data: &'static [u8],
+        header_size: usize,
+        width: usize,
+        height: usize,
+        bytes_per_glyph: usize,
+        nb_glyphs: usize,
+    ) -> Result<Font, Errcode> {
+        let bytes_per_row = (width + 7) >> 3;
+        if width == 0 || height == 0 || bytes_per_glyph < bytes_per_row * height {
+            return Err(Errcode::FontInvalid);
+        }
+        let glyphs = data
+            .get(header_size..)
+            .and_then(|glyphs| glyphs.get(..bytes_per_glyph.checked_mul(nb_glyphs)?))
+            .ok_or(Errcode::FontInvalid)?;
+        Ok(Font {
+            width,
+            height,
+            bytes_per_row,
+            bytes_per_glyph,
+            nb_glyphs,
+            glyphs,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // Whether the pixel (x, y) of the glyph of `c` is set, outside of the glyph is never set
+    pub fn pixel(&self, c: char, x: isize, y: isize) -> bool {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return false;
+        }
+        let (x, y) = (x as usize, y as usize);
+        let byte = self.glyph(c)[y * self.bytes_per_row + x / 8];
+        (byte & (0x80 >> (x % 8))) != 0
+    }
+
+    fn glyph(&self, c: char) -> &[u8] {
+        let index = match c as usize {
+            index if index < self.nb_glyphs => index,
+            _ => REPLACEMENT_CHAR as usize,
+        };
+        &self.glyphs[index * self.bytes_per_glyph..][..self.bytes_per_glyph]
+    }
+}
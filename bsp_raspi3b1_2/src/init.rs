@@ -1,6 +1,15 @@
+use crate::allocator;
+use crate::board;
+use crate::cpu;
+use crate::drivers::{gpio, IRQ, THERMAL, TIMER};
 use crate::errors::Errcode;
+use crate::mmu;
+use crate::monitor;
+use crate::power::{self, Device};
 
 // First to be called
+//    Map the memory and enable the caches
+//    Make sure we run on the expected board
 //    Initialize default values everywhere
 //    Disable all devices by default
 //    Set all pins as input by default
@@ -8,32 +17,47 @@ use crate::errors::Errcode;
 //    Init allocator
 //    Init GPU
 pub fn init_bsp() -> Result<(), Errcode> {
+    init_mmu()?;
+    board::check_board()?;
+    disable_all_devices()?;
+    init_irq_controller()?;
+    init_timer()?;
+    init_allocator()?;
     init_drivers()?;
     Ok(())
 }
 
 fn init_drivers() -> Result<(), Errcode> {
+    THERMAL.enable();
+    monitor::register_command()?;
+    gpio::register_command()?;
     Ok(())
 }
 
 fn init_allocator() -> Result<(), Errcode> {
-    todo!();
+    allocator::init_heap();
+    Ok(())
 }
 
 fn disable_all_devices() -> Result<(), Errcode> {
-    todo!();
+    // Except the serial console, set up by the chainloader or the firmware
+    power::acquire(Device::Uart0)?;
+    power::power_off_unused();
+    Ok(())
+}
+
+fn init_mmu() -> Result<(), Errcode> {
+    mmu::init();
+    mmu::enable_caches();
+    Ok(())
 }
 
 fn init_irq_controller() -> Result<(), Errcode> {
-    todo!();
+    IRQ.reset();
+    cpu::irq_unmask();
+    Ok(())
 }
 
 fn init_timer() -> Result<(), Errcode> {
-    todo!();
+    TIMER.enable_irq()
 }
-
-// IRQ register
-
-// pub fn register_irq(nb: u32, handler: Box<dyn FnOnce()>) {
-//     todo!();
-// }
@@ -0,0 +1,456 @@
+use crate::drivers::gpio::{GpioEvent, Pull, GPIO};
+use crate::drivers::pins::{mode, Pin, Refused};
+use crate::drivers::TIMER;
+use crate::errors::Errcode;
+use crate::sync::SpinLock;
+
+pub const MAX_BUTTONS: usize = 8;
+pub const MAX_ENCODERS: usize = 4;
+pub const QUEUE_SIZE: usize = 32;
+
+// Owner of the pins in the GPIO registry
+const INPUT_OWNER: &str = "input";
+
+// Quarter steps for each (previous AB, current AB) transition of a quadrature encoder
+//    Transitions skipping a state are ignored, so a bouncing contact goes back and forth between
+//    two states and cancels itself out.
+const QUADRATURE_STEPS: [i8; 16] = [0, -1, 1, 0, 1, 0, 0, -1, -1, 0, 0, 1, 0, 1, -1, 0];
+
+// Debounced buttons and rotary encoders, turned into events for the main loop
+//    The GPIO IRQ only records the edges in there. `poll` has to be called from the main loop to
+//    turn them into events, the main loop then sleeps with WFE: the GPIO IRQs and the alarm set on
+//    the system timer for the next debounce or long press deadline wake it up.
+static INPUTS: SpinLock<Inputs> = SpinLock::new_irqsafe(Inputs {
+    buttons: [None; MAX_BUTTONS],
+    encoders: [None; MAX_ENCODERS],
+});
+
+static QUEUE: SpinLock<EventQueue> = SpinLock::new(EventQueue {
+    events: [None; QUEUE_SIZE],
+    head: 0,
+    len: 0,
+});
+
+// System timer slot used to wake the main loop when a deadline expires
+static ALARM: SpinLock<Option<usize>> = SpinLock::new(None);
+
+/// Handle on a registered button.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ButtonId(usize);
+
+/// Handle on a registered rotary encoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncoderId(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ButtonEvent {
+    Pressed,
+    Released,
+    // Still pressed after `long_press_us`, sent once per press
+    LongPress,
+    // Sent after the `Pressed` of the second press
+    DoubleTap,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    Button {
+        id: ButtonId,
+        event: ButtonEvent,
+        timestamp_us: u64,
+    },
+    // Detents turned since the last event, positive clockwise, acceleration included
+    Encoder {
+        id: EncoderId,
+        steps: i32,
+        timestamp_us: u64,
+    },
+}
+
+// All durations in microseconds
+#[derive(Debug, Clone, Copy)]
+pub struct ButtonTimings {
+    // The contact has to stay still this long for a press or a release to count
+    pub debounce_us: u64,
+    pub long_press_us: u64,
+    // Longest time between two presses for a double tap
+    pub double_tap_us: u64,
+}
+
+impl Default for ButtonTimings {
+    fn default() -> ButtonTimings {
+        ButtonTimings {
+            debounce_us: 10_000,
+            long_press_us: 800_000,
+            double_tap_us: 300_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct EncoderConfig {
+    // Quarter steps between two detents, 4 for most encoders
+    pub steps_per_detent: u8,
+    // Detents closer than this are multiplied, by up to `max_multiplier` when turned fast
+    //    A `max_multiplier` of 1 disables the acceleration.
+    pub acceleration_us: u64,
+    pub max_multiplier: i32,
+}
+
+impl Default for EncoderConfig {
+    fn default() -> EncoderConfig {
+        EncoderConfig {
+            steps_per_detent: 4,
+            acceleration_us: 40_000,
+            max_multiplier: 8,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Button {
+    pin: usize,
+    active_low: bool,
+    timings: ButtonTimings,
+    // Last edge seen by the IRQ, waiting for the contact to settle
+    last_edge_us: Option<u64>,
+    pressed: bool,
+    pressed_at_us: u64,
+    long_press_sent: bool,
+    // Press that could be the first half of a double tap
+    last_tap_us: Option<u64>,
+}
+
+#[derive(Clone, Copy)]
+struct Encoder {
+    pin_a: usize,
+    pin_b: usize,
+    config: EncoderConfig,
+    state: u8,
+    quarter_steps: i32,
+    last_detent_us: u64,
+    // Not sent yet
+    steps: i32,
+}
+
+struct Inputs {
+    buttons: [Option<Button>; MAX_BUTTONS],
+    encoders: [Option<Encoder>; MAX_ENCODERS],
+}
+
+// Ring buffer of the events not read yet
+struct EventQueue {
+    events: [Option<InputEvent>; QUEUE_SIZE],
+    head: usize,
+    len: usize,
+}
+
+// Button between the pin and the ground if `active_low`, or the 3.3V otherwise
+//    The internal pull keeps the pin at rest while the button is open. The pin is given back if
+//    the button cannot be added.
+pub fn add_button<const N: usize>(
+    pin: Pin<N, mode::Input>,
+    active_low: bool,
+    timings: ButtonTimings,
+) -> Result<ButtonId, Refused<Pin<N, mode::Input>>> {
+    // Cannot fail, the pin number comes from a typed pin
+    let previous_pull = GPIO.pull(N).unwrap_or(None);
+    // The slot is reserved first, a full table leaves the pin untouched
+    let reserved = INPUTS.lock(|inputs| {
+        let idx = inputs
+            .buttons
+            .iter()
+            .position(|b| b.is_none())
+            .ok_or(Errcode::InputTooManyButtons)?;
+        inputs.buttons[idx] = Some(Button {
+            pin: N,
+            active_low,
+            timings,
+            last_edge_us: None,
+            pressed: false,
+            pressed_at_us: 0,
+            // A button held at boot is not a long press
+            long_press_sent: true,
+            last_tap_us: None,
+        });
+        Ok(idx)
+    });
+    let idx = match reserved {
+        Ok(idx) => idx,
+        Err(error) => return Err(Refused { pin, error }),
+    };
+    let pin = pin.with_pull(if active_low { Pull::Up } else { Pull::Down });
+    let pressed = pin.is_high() != active_low;
+    let now = TIMER.now();
+    INPUTS.lock(|inputs| {
+        if let Some(button) = inputs.buttons[idx].as_mut() {
+            button.pressed = pressed;
+            button.pressed_at_us = now;
+        }
+    });
+    if let Err(error) = GPIO.set_irq(N, GpioEvent::BothEdges, on_edge) {
+        INPUTS.lock(|inputs| inputs.buttons[idx] = None);
+        undo_pin(N, previous_pull);
+        return Err(Refused { pin, error });
+    }
+    pin.into_runtime(INPUT_OWNER);
+    Ok(ButtonId(idx))
+}
+
+// Pins A and B of an encoder
+pub type EncoderPins<const A: usize, const B: usize> = (Pin<A, mode::Input>, Pin<B, mode::Input>);
+
+// Encoder with its common pin to the ground, and A and B pulled up
+//    Both pins are given back if the encoder cannot be added.
+pub fn add_encoder<const A: usize, const B: usize>(
+    pin_a: Pin<A, mode::Input>,
+    pin_b: Pin<B, mode::Input>,
+    config: EncoderConfig,
+) -> Result<EncoderId, Refused<EncoderPins<A, B>>> {
+    // Cannot fail, the pin numbers come from typed pins
+    let previous_pulls = (GPIO.pull(A).unwrap_or(None), GPIO.pull(B).unwrap_or(None));
+    // The slot is reserved first, a full table leaves the pins untouched
+    let reserved = INPUTS.lock(|inputs| {
+        let idx = inputs
+            .encoders
+            .iter()
+            .position(|e| e.is_none())
+            .ok_or(Errcode::InputTooManyEncoders)?;
+        inputs.encoders[idx] = Some(Encoder {
+            pin_a: A,
+            pin_b: B,
+            config,
+            state: 0,
+            quarter_steps: 0,
+            last_detent_us: 0,
+            steps: 0,
+        });
+        Ok(idx)
+    });
+    let idx = match reserved {
+        Ok(idx) => idx,
+        Err(error) => {
+            return Err(Refused {
+                pin: (pin_a, pin_b),
+                error,
+            })
+        }
+    };
+    let pin_a = pin_a.with_pull(Pull::Up);
+    let pin_b = pin_b.with_pull(Pull::Up);
+    let state = u8::from(pin_a.is_high()) << 1 | u8::from(pin_b.is_high());
+    INPUTS.lock(|inputs| {
+        if let Some(encoder) = inputs.encoders[idx].as_mut() {
+            encoder.state = state;
+        }
+    });
+    let irqs = GPIO
+        .set_irq(A, GpioEvent::BothEdges, on_edge)
+        .and_then(|_| GPIO.set_irq(B, GpioEvent::BothEdges, on_edge));
+    if let Err(error) = irqs {
+        INPUTS.lock(|inputs| inputs.encoders[idx] = None);
+        undo_pin(A, previous_pulls.0);
+        undo_pin(B, previous_pulls.1);
+        return Err(Refused {
+            pin: (pin_a, pin_b),
+            error,
+        });
+    }
+    pin_a.into_runtime(INPUT_OWNER);
+    pin_b.into_runtime(INPUT_OWNER);
+    Ok(EncoderId(idx))
+}
+
+// Oldest event not read yet
+pub fn next_event() -> Option<InputEvent> {
+    QUEUE.lock(|queue| queue.pop())
+}
+
+// Turn what the IRQ recorded into events, and set the alarm for the next deadline
+//    Events that do not fit in the queue are lost, reported with `InputQueueFull`.
+pub fn poll() -> Result<(), Errcode> {
+    let now = TIMER.now();
+    let mut events = [None; MAX_BUTTONS * 3 + MAX_ENCODERS];
+    let mut nb_events = 0;
+    let mut push = |event| {
+        events[nb_events] = Some(event);
+        nb_events += 1;
+    };
+
+    let mut next_deadline = None;
+    INPUTS.lock(|inputs| -> Result<(), Errcode> {
+        for (idx, button) in inputs.buttons.iter_mut().enumerate() {
+            let Some(button) = button else { continue };
+            let id = ButtonId(idx);
+            if let Some(edge_us) = button.last_edge_us {
+                let settled_us = edge_us + button.timings.debounce_us;
+                if now < settled_us {
+                    next_deadline = earliest(next_deadline, settled_us);
+                } else {
+                    button.last_edge_us = None;
+                    let pressed = is_active(button.pin, button.active_low)?;
+                    if pressed != button.pressed {
+                        button.pressed = pressed;
+                        button.update(id, edge_us, &mut push);
+                    }
+                }
+            }
+            if button.pressed && !button.long_press_sent {
+                let long_press_us = button.pressed_at_us + button.timings.long_press_us;
+                if now >= long_press_us {
+                    button.long_press_sent = true;
+                    button.last_tap_us = None;
+                    push(InputEvent::Button {
+                        id,
+                        event: ButtonEvent::LongPress,
+                        timestamp_us: long_press_us,
+                    });
+                } else {
+                    next_deadline = earliest(next_deadline, long_press_us);
+                }
+            }
+        }
+        for (idx, encoder) in inputs.encoders.iter_mut().enumerate() {
+            if let Some(encoder) = encoder.as_mut().filter(|e| e.steps != 0) {
+                push(InputEvent::Encoder {
+                    id: EncoderId(idx),
+                    steps: encoder.steps,
+                    timestamp_us: encoder.last_detent_us,
+                });
+                encoder.steps = 0;
+            }
+        }
+        Ok(())
+    })?;
+
+    if let Some(deadline) = next_deadline {
+        set_alarm(deadline.saturating_sub(now))?;
+    }
+    QUEUE.lock(|queue| {
+        for event in events.into_iter().flatten() {
+            queue.push(event)?;
+        }
+        Ok(())
+    })
+}
+
+impl Button {
+    // The debounced state changed at `timestamp_us`
+    fn update(&mut self, id: ButtonId, timestamp_us: u64, push: &mut impl FnMut(InputEvent)) {
+        let event = |event| InputEvent::Button {
+            id,
+            event,
+            timestamp_us,
+        };
+        if !self.pressed {
+            push(event(ButtonEvent::Released));
+            return;
+        }
+        push(event(ButtonEvent::Pressed));
+        self.pressed_at_us = timestamp_us;
+        self.long_press_sent = false;
+        match self.last_tap_us {
+            Some(tap_us) if timestamp_us.saturating_sub(tap_us) <= self.timings.double_tap_us => {
+                push(event(ButtonEvent::DoubleTap));
+                self.last_tap_us = None;
+            }
+            _ => self.last_tap_us = Some(timestamp_us),
+        }
+    }
+}
+
+impl Encoder {
+    // Called from the IRQ with the new level of the pins
+    fn update(&mut self, state: u8, now: u64) {
+        let transition = usize::from(self.state << 2 | state);
+        self.state = state;
+        self.quarter_steps += i32::from(QUADRATURE_STEPS[transition]);
+
+        let steps_per_detent = i32::from(self.config.steps_per_detent.max(1));
+        if self.quarter_steps.abs() < steps_per_detent {
+            return;
+        }
+        let direction = self.quarter_steps.signum();
+        self.quarter_steps -= direction * steps_per_detent;
+
+        let elapsed_us = now.saturating_sub(self.last_detent_us).max(1);
+        let multiplier = (self.config.acceleration_us / elapsed_us)
+            .clamp(1, self.config.max_multiplier.max(1) as u64) as i32;
+        self.last_detent_us = now;
+        self.steps = self.steps.saturating_add(direction * multiplier);
+    }
+}
+
+impl EventQueue {
+    fn push(&mut self, event: InputEvent) -> Result<(), Errcode> {
+        if self.len == QUEUE_SIZE {
+            return Err(Errcode::InputQueueFull);
+        }
+        self.events[(self.head + self.len) % QUEUE_SIZE] = Some(event);
+        self.len += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<InputEvent> {
+        if self.len == 0 {
+            return None;
+        }
+        let event = self.events[self.head].take();
+        self.head = (self.head + 1) % QUEUE_SIZE;
+        self.len -= 1;
+        event
+    }
+}
+
+// GPIO IRQ handler of every pin of the module
+fn on_edge(pin: usize) {
+    let now = TIMER.now();
+    INPUTS.lock(|inputs| {
+        for button in inputs.buttons.iter_mut().flatten() {
+            if button.pin == pin {
+                button.last_edge_us = Some(now);
+            }
+        }
+        for encoder in inputs.encoders.iter_mut().flatten() {
+            if encoder.pin_a == pin || encoder.pin_b == pin {
+                // Both pins are valid, they were configured
+                if let Ok(state) = encoder_state(encoder.pin_a, encoder.pin_b) {
+                    encoder.update(state, now);
+                }
+            }
+        }
+    });
+}
+
+// Put back a pin whose registration failed as it was, as far as we know
+fn undo_pin(pin: usize, previous_pull: Option<Pull>) {
+    // Cannot fail, the pin number comes from a typed pin
+    let _ = GPIO.clear_irq(pin);
+    if let Some(pull) = previous_pull {
+        let _ = GPIO.set_pull(&[pin], pull);
+    }
+}
+
+fn is_active(pin: usize, active_low: bool) -> Result<bool, Errcode> {
+    Ok(GPIO.get_pin_state(pin)? != active_low)
+}
+
+fn encoder_state(pin_a: usize, pin_b: usize) -> Result<u8, Errcode> {
+    let a = GPIO.get_pin_state(pin_a)?;
+    let b = GPIO.get_pin_state(pin_b)?;
+    Ok(u8::from(a) << 1 | u8::from(b))
+}
+
+fn earliest(deadline: Option<u64>, other: u64) -> Option<u64> {
+    Some(deadline.map_or(other, |deadline| deadline.min(other)))
+}
+
+fn set_alarm(time_us: u64) -> Result<(), Errcode> {
+    ALARM.lock(|alarm| match *alarm {
+        Some(timer_nb) => TIMER.set(timer_nb, time_us),
+        None => {
+            *alarm = Some(TIMER.register_new(time_us)?);
+            Ok(())
+        }
+    })
+}
@@ -1,59 +1,79 @@
 #![allow(dead_code, unused_variables)]
 #![no_std]
 
+extern crate alloc;
+
 #[cfg(not(feature = "builder"))]
 mod boot;
 mod cpu;
+mod exceptions;
 mod mailboxes;
 mod memory;
 mod sync;
 
-pub use cpu::spin_for_cycles;
+pub use drivers::generic_timer::{delay_ms, delay_us};
 
+pub mod allocator;
+pub mod board;
+pub mod clocks;
 pub mod console;
 pub mod drivers;
 pub mod errors;
+pub mod font;
 pub mod init;
+pub mod input;
+pub mod mmu;
+pub mod monitor;
+pub mod power;
 pub mod screen;
 
-const MAX_CHAINLOAD_BINARY_SIZE: u32 = u32::MAX; // TODO    To define
+// The chainloader relocates itself there (see kernel.ld), the loaded kernel must end before it
+const CHAINLOADER_RELOCATED_ADDRESS: usize = 0x200_0000;
+const MAX_CHAINLOAD_BINARY_SIZE: u32 =
+    (CHAINLOADER_RELOCATED_ADDRESS - memory::BOARD_DEFAULT_LOAD_ADDRESS) as u32;
 
 #[cfg(feature = "builder")]
 pub const LINKER_SCRIPT: &str = include_str!("kernel.ld");
 
-pub fn chainloader_binary_load(uart: &drivers::uart::UartDriver) -> ! {
-    assert!(
-        uart.init.lock(|i| *i),
-        "Cannot chainload: UART not initialized"
-    );
+// Receive a kernel on the UART and jump to it, only returns if it cannot start
+pub fn chainloader_binary_load(
+    uart: &drivers::uart::UartDriver,
+) -> Result<core::convert::Infallible, errors::Errcode> {
+    // The loaded kernel would overwrite our read-only code, and could sit in the data cache
+    if mmu::is_enabled() {
+        return Err(errors::Errcode::ChainloadMmuEnabled);
+    }
+    if !uart.init.lock(|i| *i) {
+        return Err(errors::Errcode::DeviceNotInitialized("UART"));
+    }
     uart.flush();
     uart.clear_rx();
     loop {
         uart.write("333"); // INIT
-        let c = uart.read_char(true).unwrap();
-        if c == 'u' {
+        if read_byte(uart)? == b'u' {
             break;
         }
     }
 
     // Read the binary's size.
-    let mut size: u32 = u32::from(uart.read_char(true).unwrap() as u8);
-    size |= u32::from(uart.read_char(true).unwrap() as u8) << 8;
-    size |= u32::from(uart.read_char(true).unwrap() as u8) << 16;
-    size |= u32::from(uart.read_char(true).unwrap() as u8) << 24;
-    assert!(size < MAX_CHAINLOAD_BINARY_SIZE);
+    let mut size: u32 = u32::from(read_byte(uart)?);
+    size |= u32::from(read_byte(uart)?) << 8;
+    size |= u32::from(read_byte(uart)?) << 16;
+    size |= u32::from(read_byte(uart)?) << 24;
+    if size > MAX_CHAINLOAD_BINARY_SIZE {
+        return Err(errors::Errcode::ChainloadImageTooLarge(size));
+    }
 
     uart.write("OK");
 
     let kernel_addr: *mut u8 = memory::BOARD_DEFAULT_LOAD_ADDRESS as *mut u8;
 
     // Read the kernel byte by byte.
+    let size = usize::try_from(size).map_err(|_| errors::Errcode::ChainloadImageTooLarge(size))?;
     for i in 0..size {
+        let byte = read_byte(uart)?;
         unsafe {
-            core::ptr::write_volatile(
-                kernel_addr.offset(i.try_into().unwrap()),
-                uart.read_byte(true).unwrap(),
-            );
+            core::ptr::write_volatile(kernel_addr.add(i), byte);
         }
     }
     uart.write("OK");
@@ -61,3 +81,8 @@ pub fn chainloader_binary_load(uart: &drivers::uart::UartDriver) -> ! {
     let kernel: fn() -> ! = unsafe { core::mem::transmute(kernel_addr) };
     kernel()
 }
+
+fn read_byte(uart: &drivers::uart::UartDriver) -> Result<u8, errors::Errcode> {
+    uart.read_byte(true)
+        .ok_or(errors::Errcode::ChainloadUartRead)
+}
@@ -5,9 +5,223 @@
 // Implementation idea
 // https://github.com/Knight-Ops/raspi-os/blob/master/src/bsp/driver/bcm/bcm2xxx_mailbox/bcm2837_mail.rs
 
+use core::time::Duration;
+
+use tock_registers::interfaces::{Readable, Writeable};
+use tock_registers::registers::{ReadOnly, WriteOnly};
+use tock_registers::{register_bitfields, register_structs};
+
+use crate::drivers::generic_timer::Instant;
+use crate::errors::Errcode;
+use crate::memory::{phys_to_bus, MMIODerefWrapper, MAILBOX_BASE};
+use crate::mmu;
+use crate::sync::SpinLock;
+
+const CHANNEL_PROPERTY_TAGS: u32 = 8;
+const CHANNEL_MASK: u32 = 0xF;
+
+const BUFFER_WORDS: usize = 256;
+pub(crate) const MAX_TAGS: usize = 16;
+const MAX_TAG_VALUE_WORDS: usize = 8;
+
+// Header (size, code) plus the end tag
+const MESSAGE_OVERHEAD_WORDS: usize = 3;
+// Ident, value buffer size, request / response code
+const TAG_HEADER_WORDS: usize = 3;
+
+const CODE_REQUEST: u32 = 0;
+const CODE_RESPONSE_SUCCESS: u32 = 0x8000_0000;
+const CODE_RESPONSE_ERROR: u32 = 0x8000_0001;
+const TAG_RESPONSE: u32 = 1 << 31;
+const TAG_END: u32 = 0;
+
+// Allocating a framebuffer can keep the firmware busy for a while
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub(crate) static MAILBOX: MailboxDriver = MailboxDriver::init();
+
+// Property channel of the VideoCore mailbox
+//    A single request is in flight at a time, the lock is held until the firmware answers, so
+//    this must not be used from IRQ handlers.
+pub(crate) struct MailboxDriver {
+    inner: SpinLock<MailboxInner>,
+}
+
+struct MailboxInner {
+    registers: Registers,
+    buffer: PropertyBuffer,
+}
+
+// The firmware only gets the upper 28 bits of the address, a cache line alignment also keeps
+//    the cache maintenance from touching anything else.
+#[repr(C, align(64))]
+struct PropertyBuffer([u32; BUFFER_WORDS]);
+
+impl MailboxDriver {
+    const fn init() -> MailboxDriver {
+        MailboxDriver {
+            inner: SpinLock::new(MailboxInner {
+                registers: Registers::new(MAILBOX_BASE),
+                buffer: PropertyBuffer([0; BUFFER_WORDS]),
+            }),
+        }
+    }
+
+    // Send every tag of the request in one message, and decode the replies in the same order
+    pub(crate) fn send(&self, request: &PropertyRequest) -> Result<PropertyResponse, Errcode> {
+        self.inner.lock(|inner| {
+            let buffer = &mut inner.buffer.0;
+            request.encode(buffer);
+
+            let addr = buffer.as_ptr() as usize;
+            mmu::clean_dcache_range(addr, core::mem::size_of_val(buffer));
+            let message = (phys_to_bus(addr) & !CHANNEL_MASK) | CHANNEL_PROPERTY_TAGS;
+            inner.registers.call(message)?;
+            mmu::invalidate_dcache_range(addr, core::mem::size_of_val(buffer));
+
+            match buffer[1] {
+                CODE_RESPONSE_SUCCESS => request.decode(buffer),
+                // CODE_RESPONSE_ERROR, or the request was not even parsed
+                _ => Err(Errcode::MailboxRequestFailed),
+            }
+        })
+    }
+
+    // Shortcut for a request made of a single tag
+    pub(crate) fn query(&self, tag: RpiMailboxTag) -> Result<RpiMailboxReply, Errcode> {
+        let mut request = PropertyRequest::new();
+        request.add(tag)?;
+        self.send(&request)?
+            .into_iter()
+            .next()
+            .ok_or(Errcode::MailboxTagNotAnswered(tag.id()))
+    }
+}
+
+impl Registers {
+    // Post a message on the property channel, then wait for the answer of the firmware
+    fn call(&self, message: u32) -> Result<(), Errcode> {
+        let deadline = Instant::now() + RESPONSE_TIMEOUT;
+        while self.WRITE_STATUS.is_set(STATUS::FULL) {
+            if Instant::now() > deadline {
+                return Err(Errcode::MailboxTimeout);
+            }
+            core::hint::spin_loop();
+        }
+        self.WRITE.set(message);
+
+        loop {
+            while self.READ_STATUS.is_set(STATUS::EMPTY) {
+                if Instant::now() > deadline {
+                    return Err(Errcode::MailboxTimeout);
+                }
+                core::hint::spin_loop();
+            }
+            // Messages of the other channels are not for us
+            if self.READ.get() == message {
+                return Ok(());
+            }
+        }
+    }
+}
+
+// Tags to be sent together in one message
+pub(crate) struct PropertyRequest {
+    tags: [Option<RpiMailboxTag>; MAX_TAGS],
+    nb_tags: usize,
+    words: usize,
+}
+
+impl PropertyRequest {
+    pub(crate) fn new() -> PropertyRequest {
+        PropertyRequest {
+            tags: [None; MAX_TAGS],
+            nb_tags: 0,
+            words: MESSAGE_OVERHEAD_WORDS,
+        }
+    }
+
+    pub(crate) fn add(&mut self, tag: RpiMailboxTag) -> Result<&mut PropertyRequest, Errcode> {
+        let words = TAG_HEADER_WORDS + tag.value_buffer_words()?;
+        if self.nb_tags == MAX_TAGS || self.words + words > BUFFER_WORDS {
+            return Err(Errcode::MailboxRequestTooLarge);
+        }
+        self.tags[self.nb_tags] = Some(tag);
+        self.nb_tags += 1;
+        self.words += words;
+        Ok(self)
+    }
+
+    fn tags(&self) -> impl Iterator<Item = &RpiMailboxTag> {
+        self.tags[..self.nb_tags].iter().flatten()
+    }
+
+    // Only called on requests accepted by `add`, so everything fits
+    fn encode(&self, buffer: &mut [u32; BUFFER_WORDS]) {
+        buffer[0] = (self.words * 4) as u32;
+        buffer[1] = CODE_REQUEST;
+        let mut offset = 2;
+        for tag in self.tags() {
+            let (values, len) = tag
+                .request_values()
+                .unwrap_or(([0; MAX_TAG_VALUE_WORDS], 0));
+            let value_words = tag.value_buffer_words().unwrap_or(0);
+            buffer[offset] = tag.id();
+            buffer[offset + 1] = (value_words * 4) as u32;
+            buffer[offset + 2] = CODE_REQUEST;
+            let value_buffer = &mut buffer[offset + TAG_HEADER_WORDS..][..value_words];
+            value_buffer.fill(0);
+            value_buffer[..len].copy_from_slice(&values[..len]);
+            offset += TAG_HEADER_WORDS + value_words;
+        }
+        buffer[offset] = TAG_END;
+    }
+
+    fn decode(&self, buffer: &[u32; BUFFER_WORDS]) -> Result<PropertyResponse, Errcode> {
+        let mut response = PropertyResponse {
+            replies: [None; MAX_TAGS],
+        };
+        let mut offset = 2;
+        for (tag, reply) in self.tags().zip(response.replies.iter_mut()) {
+            let value_words = tag.value_buffer_words()?;
+            let code = buffer[offset + 2];
+            if (code & TAG_RESPONSE) == 0 {
+                return Err(Errcode::MailboxTagNotAnswered(tag.id()));
+            }
+            // The firmware tells how much it wanted to write, it may not have fit
+            let len_bytes = (code & !TAG_RESPONSE) as usize;
+            let len = ((len_bytes + 3) >> 2).min(value_words);
+            let values = &buffer[offset + TAG_HEADER_WORDS..][..len];
+            *reply = Some(tag.decode(values)?);
+            offset += TAG_HEADER_WORDS + value_words;
+        }
+        Ok(response)
+    }
+}
+
+impl Default for PropertyRequest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Replies of the firmware, in the order of the tags of the request
+pub(crate) struct PropertyResponse {
+    replies: [Option<RpiMailboxReply>; MAX_TAGS],
+}
+
+impl IntoIterator for PropertyResponse {
+    type Item = RpiMailboxReply;
+    type IntoIter = core::iter::Flatten<core::array::IntoIter<Option<RpiMailboxReply>, MAX_TAGS>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.replies.into_iter().flatten()
+    }
+}
+
 #[repr(u32)]
-#[derive(Debug)]
-enum RpiMailboxTag {
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum RpiMailboxTag {
     /* Videocore */
     GetFirmwareVersion = 0x1,
 
@@ -37,15 +251,18 @@ enum RpiMailboxTag {
 
     /* Clocks */
     GetClockState(TagClockId) = 0x30001,
-    SetClockState(bool) = 0x38001,
+    SetClockState {
+        clock_id: TagClockId,
+        on: bool,
+    } = 0x38001,
     GetClockRate(TagClockId) = 0x30002,
 
     /*    Onboard led status     */
-    GetOnboardLedStatus = 0x34041,
+    GetOnboardLedStatus = 0x30041,
     SetOnboardLedStatus {
         pin_number: u32,
         status: bool,
-    },
+    } = 0x38041,
 
     /*    Back to clocks     */
     GetClockRateMeasured(TagClockId) = 0x30047, // Real measured freq, not theoretical one
@@ -154,13 +371,16 @@ enum RpiMailboxTag {
         left: u32,
     } = 0x4800A,
 
+    // Returns once the next vertical blanking starts, not implemented by every firmware
+    WaitForVsync = 0x4000E,
+
     GetPalette = 0x4000B,
     TestPalette {
         offset: usize,
         length: usize,
         // palettes: Vec<u32>,
     } = 0x4400B,
-    SetPaletsize {
+    SetPalette {
         offset: usize,
         length: usize,
         // palettes: Vec<u32>,
@@ -172,13 +392,13 @@ enum RpiMailboxTag {
         ptr: u32,
         hotspot_x: u32,
         hotspot_y: u32,
-    } = 0x8011,
+    } = 0x8010,
     SetCursorState {
         enable: bool,
         x: u32,
         y: u32,
         coords_from_framebuffer: bool, // If false, coords from display instead
-    } = 0x8010,
+    } = 0x8011,
 
     SetScreenGamma {
         display_number: u32,
@@ -186,13 +406,13 @@ enum RpiMailboxTag {
     } = 0x8012,
 }
 
-#[repr(u32)]
-#[derive(Debug)]
-enum RpiMailboxReply {
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum RpiMailboxReply {
     OperationSuccess(bool),
     FirmwareVersion(u32),
     BoardModel(u32),
-    MacAddress(u8, u8, u8),
+    BoardRevision(u32),
+    MacAddress([u8; 6]),
     BoardSerial(u64),
     ArmMemory {
         base: u32,
@@ -211,7 +431,7 @@ enum RpiMailboxReply {
         not_exists: bool,
     },
     PowerTiming {
-        devide_id: TagDeviceId,
+        device_id: TagDeviceId,
         wait_micro_sec: u32,
     }, // Wait N us before stable
     TagClockState {
@@ -273,9 +493,347 @@ enum RpiMailboxReply {
 }
 
 impl RpiMailboxTag {
-    // fn send(&self) -> Vec<u32> {
-    //     todo!();
-    // }
+    pub(crate) fn id(&self) -> u32 {
+        // A `repr(u32)` enum starts with its discriminant, which is the tag ident
+        unsafe { *(self as *const RpiMailboxTag as *const u32) }
+    }
+
+    // Values sent along with the tag, and their number
+    fn request_values(&self) -> Result<([u32; MAX_TAG_VALUE_WORDS], usize), Errcode> {
+        let mut values = [0; MAX_TAG_VALUE_WORDS];
+        let request: &[u32] = match *self {
+            RpiMailboxTag::GetFirmwareVersion
+            | RpiMailboxTag::GetBoardModel
+            | RpiMailboxTag::GetBoardRevision
+            | RpiMailboxTag::GetBoardMacAddress
+            | RpiMailboxTag::GetBoardSerial
+            | RpiMailboxTag::GetArmMemory
+            | RpiMailboxTag::GetVcMemory
+            | RpiMailboxTag::GetDmaChannels
+            | RpiMailboxTag::GetOnboardLedStatus
+            | RpiMailboxTag::ReleaseBuffer
+            | RpiMailboxTag::GetPhysicalSize
+            | RpiMailboxTag::GetVirtualSize
+            | RpiMailboxTag::GetDepth
+            | RpiMailboxTag::GetPixelOrder
+            | RpiMailboxTag::GetAlphaMode
+            | RpiMailboxTag::GetPitch
+            | RpiMailboxTag::GetVirtualOffset
+            | RpiMailboxTag::GetOverscan => &[],
+
+            // Turbo and temperature take an id, always 0, waiting for vsync a value ignored by the firmware
+            RpiMailboxTag::GetTurbo
+            | RpiMailboxTag::GetTemperature
+            | RpiMailboxTag::GetMaxTemperature
+            | RpiMailboxTag::WaitForVsync => &[0],
+            RpiMailboxTag::SetTurbo(on) => &[0, u32::from(on)],
+
+            RpiMailboxTag::GetPowerState(device_id) | RpiMailboxTag::GetTiming(device_id) => {
+                &[device_id as u32]
+            }
+            RpiMailboxTag::SetPowerState {
+                device_id,
+                on,
+                wait,
+            } => &[device_id as u32, u32::from(on) | (u32::from(wait) << 1)],
+
+            RpiMailboxTag::GetClockState(clock_id)
+            | RpiMailboxTag::GetClockRate(clock_id)
+            | RpiMailboxTag::GetClockRateMeasured(clock_id)
+            | RpiMailboxTag::GetMaxClockRate(clock_id)
+            | RpiMailboxTag::GetMinClockRate(clock_id) => &[clock_id as u32],
+            RpiMailboxTag::SetClockState { clock_id, on } => &[clock_id as u32, u32::from(on)],
+            RpiMailboxTag::SetClockRate {
+                clock_id,
+                rate,
+                skip_set_turbo,
+            } => &[clock_id as u32, rate, u32::from(skip_set_turbo)],
+
+            RpiMailboxTag::SetOnboardLedStatus { pin_number, status } => {
+                &[pin_number, u32::from(status)]
+            }
+
+            RpiMailboxTag::GetVoltage(voltage_id)
+            | RpiMailboxTag::GetMaxVoltage(voltage_id)
+            | RpiMailboxTag::GetMinVoltage(voltage_id) => &[voltage_id as u32],
+            RpiMailboxTag::SetVoltage { voltage_id, value } => &[voltage_id as u32, value],
+
+            RpiMailboxTag::GpuAllocateMemory {
+                size,
+                alignment,
+                flags,
+            } => &[size, alignment, flags as u32],
+            RpiMailboxTag::GpuLockMemory(handle)
+            | RpiMailboxTag::GpuUnlockMemory(handle)
+            | RpiMailboxTag::GpuReleaseMemory(handle) => &[handle],
+            RpiMailboxTag::GpuExecuteCode {
+                fp,
+                r0,
+                r1,
+                r2,
+                r3,
+                r4,
+                r5,
+            } => &[fp, r0, r1, r2, r3, r4, r5],
+            RpiMailboxTag::GetDispmanxMemHandle(handle) => &[handle],
+
+            RpiMailboxTag::AllocateBuffer(alignment) => &[alignment],
+            RpiMailboxTag::BlankScreen(on) => &[u32::from(on)],
+            RpiMailboxTag::TestPhysicalSize { width, height }
+            | RpiMailboxTag::SetPhysicalSize { width, height }
+            | RpiMailboxTag::TestVirtualSize { width, height }
+            | RpiMailboxTag::SetVirtualSize { width, height } => &[width, height],
+            RpiMailboxTag::TestDepth(depth) | RpiMailboxTag::SetDepth(depth) => &[depth],
+            RpiMailboxTag::TestPixelOrder(order) | RpiMailboxTag::SetPixelOrder(order) => {
+                &[order as u32]
+            }
+            RpiMailboxTag::TestAlphaMode(mode) | RpiMailboxTag::SetAlphaMode(mode) => {
+                &[mode as u32]
+            }
+            RpiMailboxTag::TestVirtualOffset { x, y }
+            | RpiMailboxTag::SetVirtualOffset { x, y } => &[x, y],
+            RpiMailboxTag::TestOverscan {
+                top,
+                bottom,
+                right,
+                left,
+            }
+            | RpiMailboxTag::SetOverscan {
+                top,
+                bottom,
+                right,
+                left,
+            } => &[top, bottom, left, right],
+
+            RpiMailboxTag::SetCursorInfo {
+                width,
+                height,
+                ptr,
+                hotspot_x,
+                hotspot_y,
+            } => &[width, height, 0, ptr, hotspot_x, hotspot_y],
+            RpiMailboxTag::SetCursorState {
+                enable,
+                x,
+                y,
+                coords_from_framebuffer,
+            } => &[u32::from(enable), x, y, u32::from(!coords_from_framebuffer)],
+            RpiMailboxTag::SetScreenGamma {
+                display_number,
+                gamma_table_addr,
+            } => &[display_number, gamma_table_addr],
+
+            // Variable length replies, or data to be sent along, not supported
+            RpiMailboxTag::GetClocks
+            | RpiMailboxTag::GetCommandLine
+            | RpiMailboxTag::GetEdidBlock(_)
+            | RpiMailboxTag::GetPalette
+            | RpiMailboxTag::TestPalette { .. }
+            | RpiMailboxTag::SetPalette { .. } => {
+                return Err(Errcode::MailboxUnsupportedTag(self.id()))
+            }
+        };
+        values[..request.len()].copy_from_slice(request);
+        Ok((values, request.len()))
+    }
+
+    // Number of values the firmware answers with
+    fn response_words(&self) -> usize {
+        match self {
+            RpiMailboxTag::ReleaseBuffer => 0,
+            RpiMailboxTag::GetFirmwareVersion
+            | RpiMailboxTag::GetBoardModel
+            | RpiMailboxTag::GetBoardRevision
+            | RpiMailboxTag::GetDmaChannels
+            | RpiMailboxTag::GpuAllocateMemory { .. }
+            | RpiMailboxTag::GpuLockMemory(_)
+            | RpiMailboxTag::GpuUnlockMemory(_)
+            | RpiMailboxTag::GpuReleaseMemory(_)
+            | RpiMailboxTag::GpuExecuteCode { .. }
+            | RpiMailboxTag::BlankScreen(_)
+            | RpiMailboxTag::GetDepth
+            | RpiMailboxTag::TestDepth(_)
+            | RpiMailboxTag::SetDepth(_)
+            | RpiMailboxTag::GetPixelOrder
+            | RpiMailboxTag::TestPixelOrder(_)
+            | RpiMailboxTag::SetPixelOrder(_)
+            | RpiMailboxTag::GetAlphaMode
+            | RpiMailboxTag::TestAlphaMode(_)
+            | RpiMailboxTag::SetAlphaMode(_)
+            | RpiMailboxTag::GetPitch
+            | RpiMailboxTag::SetCursorInfo { .. }
+            | RpiMailboxTag::SetCursorState { .. }
+            | RpiMailboxTag::SetScreenGamma { .. }
+            | RpiMailboxTag::WaitForVsync => 1,
+            RpiMailboxTag::GetOverscan
+            | RpiMailboxTag::TestOverscan { .. }
+            | RpiMailboxTag::SetOverscan { .. } => 4,
+            _ => 2,
+        }
+    }
+
+    // Size of the value buffer, it has to hold both the request and the response
+    fn value_buffer_words(&self) -> Result<usize, Errcode> {
+        let (_, request_len) = self.request_values()?;
+        Ok(request_len.max(self.response_words()))
+    }
+
+    fn decode(&self, values: &[u32]) -> Result<RpiMailboxReply, Errcode> {
+        if values.len() < self.response_words() {
+            return Err(Errcode::MailboxInvalidReply(self.id()));
+        }
+        let reply = match *self {
+            RpiMailboxTag::GetFirmwareVersion => RpiMailboxReply::FirmwareVersion(values[0]),
+            RpiMailboxTag::GetBoardModel => RpiMailboxReply::BoardModel(values[0]),
+            RpiMailboxTag::GetBoardRevision => RpiMailboxReply::BoardRevision(values[0]),
+            RpiMailboxTag::GetBoardMacAddress => {
+                let [a, b, c, d] = values[0].to_le_bytes();
+                let [e, f, _, _] = values[1].to_le_bytes();
+                RpiMailboxReply::MacAddress([a, b, c, d, e, f])
+            }
+            RpiMailboxTag::GetBoardSerial => {
+                RpiMailboxReply::BoardSerial(u64::from(values[0]) | (u64::from(values[1]) << 32))
+            }
+            RpiMailboxTag::GetArmMemory => RpiMailboxReply::ArmMemory {
+                base: values[0],
+                size: values[1],
+            },
+            RpiMailboxTag::GetVcMemory => RpiMailboxReply::VcMemory {
+                base: values[0],
+                size: values[1],
+            },
+            RpiMailboxTag::GetDmaChannels => RpiMailboxReply::DmaChannels(values[0]),
+
+            RpiMailboxTag::GetPowerState(device_id)
+            | RpiMailboxTag::SetPowerState { device_id, .. } => RpiMailboxReply::PowerState {
+                device_id,
+                on: (values[1] & 0b01) != 0,
+                not_exists: (values[1] & 0b10) != 0,
+            },
+            RpiMailboxTag::GetTiming(device_id) => RpiMailboxReply::PowerTiming {
+                device_id,
+                wait_micro_sec: values[1],
+            },
+
+            RpiMailboxTag::GetClockState(clock_id)
+            | RpiMailboxTag::SetClockState { clock_id, .. } => RpiMailboxReply::TagClockState {
+                clock_id,
+                on: (values[1] & 0b01) != 0,
+                not_exists: (values[1] & 0b10) != 0,
+            },
+            RpiMailboxTag::GetClockRate(clock_id)
+            | RpiMailboxTag::GetClockRateMeasured(clock_id)
+            | RpiMailboxTag::GetMaxClockRate(clock_id)
+            | RpiMailboxTag::GetMinClockRate(clock_id)
+            | RpiMailboxTag::SetClockRate { clock_id, .. } => RpiMailboxReply::TagClockRate {
+                clock_id,
+                freq: values[1],
+            },
+
+            RpiMailboxTag::GetOnboardLedStatus | RpiMailboxTag::SetOnboardLedStatus { .. } => {
+                RpiMailboxReply::OnboardLedStatus {
+                    pin_number: values[0],
+                    status: values[1],
+                }
+            }
+            RpiMailboxTag::GetTurbo | RpiMailboxTag::SetTurbo(_) => {
+                RpiMailboxReply::TurboState(values[1] != 0)
+            }
+
+            RpiMailboxTag::GetVoltage(voltage_id)
+            | RpiMailboxTag::GetMaxVoltage(voltage_id)
+            | RpiMailboxTag::GetMinVoltage(voltage_id)
+            | RpiMailboxTag::SetVoltage { voltage_id, .. } => RpiMailboxReply::VoltageState {
+                voltage_id,
+                value: values[1],
+            },
+            RpiMailboxTag::GetTemperature | RpiMailboxTag::GetMaxTemperature => {
+                RpiMailboxReply::TemperatureState(values[1])
+            }
+
+            RpiMailboxTag::GpuAllocateMemory { .. } => {
+                RpiMailboxReply::GpuAllocation(Some(values[0]).filter(|h| *h != 0))
+            }
+            RpiMailboxTag::GpuLockMemory(_) => {
+                RpiMailboxReply::LockMemory(Some(values[0]).filter(|a| *a != 0))
+            }
+            RpiMailboxTag::GpuUnlockMemory(_)
+            | RpiMailboxTag::GpuReleaseMemory(_)
+            | RpiMailboxTag::SetCursorInfo { .. }
+            | RpiMailboxTag::SetCursorState { .. }
+            | RpiMailboxTag::SetScreenGamma { .. } => {
+                RpiMailboxReply::OperationSuccess(values[0] == 0)
+            }
+            RpiMailboxTag::GpuExecuteCode { .. } => RpiMailboxReply::GpuExecutionReturn(values[0]),
+            RpiMailboxTag::GetDispmanxMemHandle(_) => RpiMailboxReply::DispmanxRessMemHandle {
+                success: values[0] == 0,
+                handle: values[1],
+            },
+
+            RpiMailboxTag::AllocateBuffer(_) => RpiMailboxReply::FramebufferAllocation {
+                base_addr: values[0],
+                buff_size: values[1],
+            },
+            RpiMailboxTag::ReleaseBuffer | RpiMailboxTag::WaitForVsync => {
+                RpiMailboxReply::OperationSuccess(true)
+            }
+            RpiMailboxTag::BlankScreen(_) => {
+                RpiMailboxReply::BlankScreenState((values[0] & 1) != 0)
+            }
+            RpiMailboxTag::GetPhysicalSize
+            | RpiMailboxTag::TestPhysicalSize { .. }
+            | RpiMailboxTag::SetPhysicalSize { .. }
+            | RpiMailboxTag::GetVirtualSize
+            | RpiMailboxTag::TestVirtualSize { .. }
+            | RpiMailboxTag::SetVirtualSize { .. } => RpiMailboxReply::ScreenSize {
+                width: values[0],
+                height: values[1],
+            },
+            RpiMailboxTag::GetDepth | RpiMailboxTag::TestDepth(_) | RpiMailboxTag::SetDepth(_) => {
+                RpiMailboxReply::ScreenDepth(values[0])
+            }
+            RpiMailboxTag::GetPixelOrder
+            | RpiMailboxTag::TestPixelOrder(_)
+            | RpiMailboxTag::SetPixelOrder(_) => RpiMailboxReply::PixelOrder(match values[0] {
+                0 => PixelOrder::Bgr,
+                1 => PixelOrder::Rgb,
+                _ => return Err(Errcode::MailboxInvalidReply(self.id())),
+            }),
+            RpiMailboxTag::GetAlphaMode
+            | RpiMailboxTag::TestAlphaMode(_)
+            | RpiMailboxTag::SetAlphaMode(_) => RpiMailboxReply::AlphaMode(match values[0] {
+                0 => AlphaMode::Enabled,
+                1 => AlphaMode::Reversed,
+                2 => AlphaMode::Ignored,
+                _ => return Err(Errcode::MailboxInvalidReply(self.id())),
+            }),
+            RpiMailboxTag::GetPitch => RpiMailboxReply::ScreenPitch(values[0]),
+            RpiMailboxTag::GetVirtualOffset
+            | RpiMailboxTag::TestVirtualOffset { .. }
+            | RpiMailboxTag::SetVirtualOffset { .. } => RpiMailboxReply::VirtualOffset {
+                x: values[0],
+                y: values[1],
+            },
+            RpiMailboxTag::GetOverscan
+            | RpiMailboxTag::TestOverscan { .. }
+            | RpiMailboxTag::SetOverscan { .. } => RpiMailboxReply::ScreenOverscan {
+                top: values[0],
+                bottom: values[1],
+                left: values[2],
+                right: values[3],
+            },
+
+            RpiMailboxTag::GetClocks
+            | RpiMailboxTag::GetCommandLine
+            | RpiMailboxTag::GetEdidBlock(_)
+            | RpiMailboxTag::GetPalette
+            | RpiMailboxTag::TestPalette { .. }
+            | RpiMailboxTag::SetPalette { .. } => {
+                return Err(Errcode::MailboxUnsupportedTag(self.id()))
+            }
+        };
+        Ok(reply)
+    }
 }
 
 #[repr(u32)]
@@ -302,8 +860,8 @@ enum TagOffset {
 }
 
 #[repr(u32)]
-#[derive(Debug)]
-enum TagClockId {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TagClockId {
     Reserved = 0,
     Emmc,
     Uart,
@@ -322,8 +880,8 @@ enum TagClockId {
 }
 
 #[repr(u32)]
-#[derive(Debug)]
-enum TagDeviceId {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagDeviceId {
     SdCard = 0,
     Uart0,
     Uart1,
@@ -336,8 +894,8 @@ enum TagDeviceId {
 }
 
 #[repr(u32)]
-#[derive(Debug)]
-enum TagVoltageId {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TagVoltageId {
     Reserved = 0,
     Core,
     SdRamC,
@@ -346,8 +904,8 @@ enum TagVoltageId {
 }
 
 #[repr(u32)]
-#[derive(Debug)]
-enum GpuAllocationFlags {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum GpuAllocationFlags {
     Normal = 0,      /* normal allocating alias. Don't use from ARM */
     Discardable = 1, /* can be resized to 0 at any time. Use for cached data */
     Coherent = 4,    /* Non-allocating in L2 but coherent */
@@ -359,16 +917,45 @@ enum GpuAllocationFlags {
 }
 
 #[repr(u32)]
-#[derive(Debug)]
-enum PixelOrder {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PixelOrder {
     Bgr,
     Rgb,
 }
 
 #[repr(u32)]
-#[derive(Debug)]
-enum AlphaMode {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AlphaMode {
     Enabled,
     Reversed,
     Ignored,
 }
+
+register_bitfields! {
+    u32,
+
+    /// Status of a mailbox, as seen from the ARM
+    STATUS [
+        FULL OFFSET(31) NUMBITS(1) [],
+        EMPTY OFFSET(30) NUMBITS(1) [],
+    ],
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        // Mailbox 0, VideoCore to ARM
+        (0x00 => READ: ReadOnly<u32>),
+        (0x04 => _reserved1),
+        (0x18 => READ_STATUS: ReadOnly<u32, STATUS::Register>),
+        (0x1C => _reserved2),
+        // Mailbox 1, ARM to VideoCore
+        (0x20 => WRITE: WriteOnly<u32>),
+        (0x24 => _reserved3),
+        (0x38 => WRITE_STATUS: ReadOnly<u32, STATUS::Register>),
+        (0x3C => @END),
+    }
+}
+
+/// Abstraction for the associated MMIO registers.
+type Registers = MMIODerefWrapper<RegisterBlock>;
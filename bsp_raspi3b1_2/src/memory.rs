@@ -43,6 +43,23 @@ pub const HDMI_BASE: usize = BASE + 0x0090_2000;
 pub const USB_BASE: usize = BASE + 0x0098_0000;
 pub const V3D_BASE: usize = BASE + 0x00C0_0000;
 
+// ARM core-local peripherals (timers and interrupt routing), outside of the BCM peripherals window
+pub const LOCAL_PERIPHERALS_BASE: usize = 0x4000_0000;
+
+// The VideoCore sees the ARM memory through bus addresses, this alias bypasses its L2 cache
+const BUS_ADDRESS_ALIAS: u32 = 0xC000_0000;
+const BUS_ADDRESS_MASK: u32 = 0x3FFF_FFFF;
+
+/// Address to give to the VideoCore (mailbox, DMA) for an ARM physical address.
+pub const fn phys_to_bus(addr: usize) -> u32 {
+    (addr as u32 & BUS_ADDRESS_MASK) | BUS_ADDRESS_ALIAS
+}
+
+/// ARM physical address of an address given by the VideoCore.
+pub const fn bus_to_phys(addr: u32) -> usize {
+    (addr & BUS_ADDRESS_MASK) as usize
+}
+
 pub struct MMIODerefWrapper<T> {
     start_addr: usize,
     phantom: PhantomData<fn() -> T>,
@@ -0,0 +1,339 @@
+use aarch64_cpu::asm::barrier;
+use aarch64_cpu::registers::{MAIR_EL1, SCTLR_EL1, TCR_EL1, TTBR0_EL1};
+use tock_registers::interfaces::{ReadWriteable, Readable, Writeable};
+use tock_registers::register_bitfields;
+
+use crate::memory::{BASE, LOCAL_PERIPHERALS_BASE};
+use crate::sync::SpinLock;
+
+// Identity mapping of the first 4 GiB with a 64 KiB granule
+//    Translation starts at level 2, each of its 8 entries covers 512 MiB. The first GiB (DRAM,
+//    then the peripherals) is described page per page by level 3 tables, the next 512 MiB
+//    (local peripherals) by a single block, the rest is left unmapped.
+const PAGE_SHIFT: usize = 16;
+const PAGE_SIZE: usize = 1 << PAGE_SHIFT;
+const L2_BLOCK_SIZE: usize = 512 * 1024 * 1024;
+const NB_L2_ENTRIES: usize = 8;
+const NB_L3_TABLES: usize = 2;
+const NB_L3_ENTRIES: usize = L2_BLOCK_SIZE / PAGE_SIZE;
+const VA_BITS: u64 = 32;
+
+// Data cache line of the Cortex-A53
+pub const CACHE_LINE_SIZE: usize = 64;
+
+// Indexes in MAIR_EL1
+const ATTR_NORMAL: u64 = 0;
+const ATTR_DEVICE: u64 = 1;
+const ATTR_NORMAL_NON_CACHEABLE: u64 = 2;
+
+static TABLES: SpinLock<TranslationTables> = SpinLock::new(TranslationTables::new());
+
+#[repr(C, align(65536))]
+struct TranslationTables {
+    // First, so that each table is aligned on its size
+    l3: [[u64; NB_L3_ENTRIES]; NB_L3_TABLES],
+    l2: [u64; NB_L2_ENTRIES],
+}
+
+// What lives at a given physical address, and how it may be accessed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MemoryKind {
+    KernelCode,
+    Normal,
+    NormalNonCacheable,
+    Device,
+}
+
+impl TranslationTables {
+    const fn new() -> TranslationTables {
+        TranslationTables {
+            l3: [[0; NB_L3_ENTRIES]; NB_L3_TABLES],
+            l2: [0; NB_L2_ENTRIES],
+        }
+    }
+
+    fn entry(&mut self, addr: usize) -> &mut u64 {
+        &mut self.l3[addr / L2_BLOCK_SIZE][(addr % L2_BLOCK_SIZE) / PAGE_SIZE]
+    }
+
+    fn populate(&mut self) {
+        let code = kernel_code_region();
+        for (table_nb, table) in self.l3.iter_mut().enumerate() {
+            for (page_nb, entry) in table.iter_mut().enumerate() {
+                let addr = table_nb * L2_BLOCK_SIZE + page_nb * PAGE_SIZE;
+                *entry = descriptor(addr, memory_kind(addr, code), true);
+            }
+        }
+
+        for (block_nb, entry) in self.l2.iter_mut().enumerate() {
+            *entry = if block_nb < NB_L3_TABLES {
+                let table = self.l3[block_nb].as_ptr() as u64;
+                (STAGE1_DESCRIPTOR::VALID::True
+                    + STAGE1_DESCRIPTOR::TYPE::TableOrPage
+                    + STAGE1_DESCRIPTOR::OUTPUT_ADDR_64KIB.val(table >> PAGE_SHIFT))
+                .value
+            } else if block_nb * L2_BLOCK_SIZE == LOCAL_PERIPHERALS_BASE {
+                descriptor(block_nb * L2_BLOCK_SIZE, MemoryKind::Device, false)
+            } else {
+                0
+            };
+        }
+    }
+}
+
+// Attributes of the page at `addr`, `code` being the range of the kernel text and rodata
+fn memory_kind(addr: usize, code: (usize, usize)) -> MemoryKind {
+    if addr >= BASE {
+        MemoryKind::Device
+    } else if addr >= code.0 && addr < code.1 {
+        MemoryKind::KernelCode
+    } else {
+        MemoryKind::Normal
+    }
+}
+
+// Level 3 page descriptor, or level 2 block descriptor, mapping `addr` to itself
+fn descriptor(addr: usize, kind: MemoryKind, page: bool) -> u64 {
+    let mut desc = STAGE1_DESCRIPTOR::VALID::True
+        + STAGE1_DESCRIPTOR::AF::True
+        + STAGE1_DESCRIPTOR::OUTPUT_ADDR_64KIB.val((addr >> PAGE_SHIFT) as u64)
+        + STAGE1_DESCRIPTOR::UXN::True;
+
+    desc += if page {
+        STAGE1_DESCRIPTOR::TYPE::TableOrPage
+    } else {
+        STAGE1_DESCRIPTOR::TYPE::Block
+    };
+
+    desc += match kind {
+        MemoryKind::KernelCode => {
+            STAGE1_DESCRIPTOR::ATTR_INDX.val(ATTR_NORMAL)
+                + STAGE1_DESCRIPTOR::SH::InnerShareable
+                + STAGE1_DESCRIPTOR::AP::RO_EL1
+                + STAGE1_DESCRIPTOR::PXN::False
+        }
+        MemoryKind::Normal => {
+            STAGE1_DESCRIPTOR::ATTR_INDX.val(ATTR_NORMAL)
+                + STAGE1_DESCRIPTOR::SH::InnerShareable
+                + STAGE1_DESCRIPTOR::AP::RW_EL1
+                + STAGE1_DESCRIPTOR::PXN::True
+        }
+        MemoryKind::NormalNonCacheable => {
+            STAGE1_DESCRIPTOR::ATTR_INDX.val(ATTR_NORMAL_NON_CACHEABLE)
+                + STAGE1_DESCRIPTOR::SH::OuterShareable
+                + STAGE1_DESCRIPTOR::AP::RW_EL1
+                + STAGE1_DESCRIPTOR::PXN::True
+        }
+        MemoryKind::Device => {
+            STAGE1_DESCRIPTOR::ATTR_INDX.val(ATTR_DEVICE)
+                + STAGE1_DESCRIPTOR::SH::OuterShareable
+                + STAGE1_DESCRIPTOR::AP::RW_EL1
+                + STAGE1_DESCRIPTOR::PXN::True
+        }
+    };
+    desc.value
+}
+
+// Text and rodata of the kernel, aligned on pages by the linker script
+fn kernel_code_region() -> (usize, usize) {
+    extern "C" {
+        static __code_start: u8;
+        static __code_end_exclusive: u8;
+    }
+    unsafe {
+        (
+            &__code_start as *const u8 as usize,
+            &__code_end_exclusive as *const u8 as usize,
+        )
+    }
+}
+
+// Build the identity mapping and turn the MMU on, caches stay off
+//    Must run on the boot core before the other cores, which then only have to call
+//    `enable_on_this_core`.
+pub(crate) fn init() {
+    assert!(!is_enabled(), "MMU already enabled");
+    TABLES.lock(|tables| tables.populate());
+    enable_on_this_core();
+}
+
+pub(crate) fn enable_on_this_core() {
+    MAIR_EL1.write(
+        MAIR_EL1::Attr0_Normal_Outer::WriteBack_NonTransient_ReadWriteAlloc
+            + MAIR_EL1::Attr0_Normal_Inner::WriteBack_NonTransient_ReadWriteAlloc
+            + MAIR_EL1::Attr1_Device::nonGathering_nonReordering_EarlyWriteAck
+            + MAIR_EL1::Attr2_Normal_Outer::NonCacheable
+            + MAIR_EL1::Attr2_Normal_Inner::NonCacheable,
+    );
+
+    TCR_EL1.write(
+        TCR_EL1::TBI0::Used
+            + TCR_EL1::IPS::Bits_32
+            + TCR_EL1::TG0::KiB_64
+            + TCR_EL1::SH0::Inner
+            + TCR_EL1::ORGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+            + TCR_EL1::IRGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+            + TCR_EL1::EPD0::EnableTTBR0Walks
+            + TCR_EL1::A1::TTBR0
+            + TCR_EL1::T0SZ.val(64 - VA_BITS)
+            + TCR_EL1::EPD1::DisableTTBR1Walks,
+    );
+
+    let l2 = TABLES.lock(|tables| tables.l2.as_ptr() as u64);
+    TTBR0_EL1.set_baddr(l2);
+
+    // Tables written with the MMU off, drop whatever the TLBs could hold
+    barrier::dsb(barrier::ISHST);
+    invalidate_tlbs();
+    barrier::dsb(barrier::ISH);
+    barrier::isb(barrier::SY);
+
+    SCTLR_EL1.modify(SCTLR_EL1::M::Enable + SCTLR_EL1::WXN::Disable);
+    barrier::isb(barrier::SY);
+}
+
+// Map a range shared with the VideoCore (framebuffer) as non-cacheable, so that every write
+//    reaches the memory without cache maintenance. Nothing may access the range meanwhile.
+pub(crate) fn set_non_cacheable(start: usize, size: usize) {
+    let first = start & !(PAGE_SIZE - 1);
+    let end = (start + size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    assert!(
+        end <= NB_L3_TABLES * L2_BLOCK_SIZE,
+        "Cannot remap {start:#x}, outside of the level 3 tables"
+    );
+
+    TABLES.lock(|tables| {
+        // Break before make, the old attributes must be gone from every TLB first
+        for page in (first..end).step_by(PAGE_SIZE) {
+            *tables.entry(page) = 0;
+        }
+        barrier::dsb(barrier::ISHST);
+        invalidate_tlbs();
+        barrier::dsb(barrier::ISH);
+
+        for page in (first..end).step_by(PAGE_SIZE) {
+            *tables.entry(page) = descriptor(page, MemoryKind::NormalNonCacheable, true);
+        }
+        barrier::dsb(barrier::ISHST);
+        barrier::isb(barrier::SY);
+    });
+
+    // Lines speculatively fetched through the old mapping
+    invalidate_dcache_range(first, end - first);
+}
+
+/// Turn the data and instruction caches on, the MMU must be enabled.
+pub fn enable_caches() {
+    assert!(is_enabled(), "Caches need the MMU to be enabled");
+    invalidate_icache();
+    SCTLR_EL1.modify(SCTLR_EL1::C::Cacheable + SCTLR_EL1::I::Cacheable);
+    barrier::isb(barrier::SY);
+}
+
+pub fn is_enabled() -> bool {
+    SCTLR_EL1.matches_all(SCTLR_EL1::M::Enable)
+}
+
+pub fn caches_enabled() -> bool {
+    SCTLR_EL1.matches_all(SCTLR_EL1::C::Cacheable + SCTLR_EL1::I::Cacheable)
+}
+
+// Write back the cached data of a range to memory, before another master (VideoCore, DMA) reads it
+pub(crate) fn clean_dcache_range(start: usize, size: usize) {
+    for line in cache_lines(start, size) {
+        #[cfg(not(feature = "builder"))]
+        unsafe {
+            core::arch::asm!("dc cvac, {}", in(reg) line, options(nostack, preserves_flags));
+        }
+    }
+    barrier::dsb(barrier::SY);
+}
+
+// Drop the cached data of a range, after another master wrote to it
+//    Lines are cleaned as well, so a partial line at the edges does not lose our own writes.
+pub(crate) fn invalidate_dcache_range(start: usize, size: usize) {
+    for line in cache_lines(start, size) {
+        #[cfg(not(feature = "builder"))]
+        unsafe {
+            core::arch::asm!("dc civac, {}", in(reg) line, options(nostack, preserves_flags));
+        }
+    }
+    barrier::dsb(barrier::SY);
+}
+
+fn cache_lines(start: usize, size: usize) -> impl Iterator<Item = usize> {
+    let first = start & !(CACHE_LINE_SIZE - 1);
+    (first..start + size).step_by(CACHE_LINE_SIZE)
+}
+
+#[inline(always)]
+fn invalidate_tlbs() {
+    #[cfg(not(feature = "builder"))]
+    unsafe {
+        core::arch::asm!("tlbi vmalle1is", options(nostack, preserves_flags));
+    }
+}
+
+#[inline(always)]
+fn invalidate_icache() {
+    #[cfg(not(feature = "builder"))]
+    unsafe {
+        core::arch::asm!(
+            "ic iallu",
+            "dsb nsh",
+            "isb",
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+register_bitfields! {
+    u64,
+
+    /// Level 2 table / block and level 3 page descriptors, 64 KiB granule
+    STAGE1_DESCRIPTOR [
+        /// Unprivileged execute never
+        UXN OFFSET(54) NUMBITS(1) [
+            False = 0,
+            True = 1
+        ],
+
+        /// Privileged execute never
+        PXN OFFSET(53) NUMBITS(1) [
+            False = 0,
+            True = 1
+        ],
+
+        /// Next level table, page or block (bits 28:16 cleared) address
+        OUTPUT_ADDR_64KIB OFFSET(16) NUMBITS(32) [],
+
+        /// Access flag, a fault is raised on first access if not set
+        AF OFFSET(10) NUMBITS(1) [
+            False = 0,
+            True = 1
+        ],
+
+        SH OFFSET(8) NUMBITS(2) [
+            OuterShareable = 0b10,
+            InnerShareable = 0b11
+        ],
+
+        AP OFFSET(6) NUMBITS(2) [
+            RW_EL1 = 0b00,
+            RO_EL1 = 0b10
+        ],
+
+        ATTR_INDX OFFSET(2) NUMBITS(3) [],
+
+        /// Block at level 2, table at level 2 or page at level 3
+        TYPE OFFSET(1) NUMBITS(1) [
+            Block = 0,
+            TableOrPage = 1
+        ],
+
+        VALID OFFSET(0) NUMBITS(1) [
+            False = 0,
+            True = 1
+        ],
+    ],
+}
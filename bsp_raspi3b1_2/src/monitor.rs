@@ -0,0 +1,237 @@
+use core::fmt;
+
+use crate::console::{self, Command};
+use crate::drivers::{THERMAL, TIMER};
+use crate::errors::Errcode;
+use crate::mailboxes::{RpiMailboxReply, RpiMailboxTag, TagVoltageId, MAILBOX};
+use crate::println;
+use crate::sync::SpinLock;
+
+pub const MAX_WATCHES: usize = 8;
+pub const DEFAULT_PERIOD_US: u64 = 1_000_000;
+
+// A threshold only clears once the reading went back this far, so a reading hovering around it
+//    does not call the callback at every sample
+const TEMPERATURE_HYSTERESIS: i32 = 2_000;
+const VOLTAGE_HYSTERESIS: u32 = 25_000;
+
+pub static MONITOR: Monitor = Monitor::init();
+
+/// One sample of the SoC sensors.
+#[derive(Debug, Clone, Copy)]
+pub struct Readings {
+    // Millidegrees Celsius
+    pub temperature: i32,
+    // Temperature at which the firmware starts throttling the clocks
+    pub max_temperature: i32,
+    // Microvolts
+    pub core_voltage: u32,
+    // System timer value when sampled
+    pub timestamp_us: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Threshold {
+    // Millidegrees Celsius, crossed when the temperature reaches it
+    TemperatureAbove(i32),
+    // Microvolts, crossed when the core voltage drops to it
+    VoltageBelow(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Crossing {
+    Crossed,
+    Cleared,
+}
+
+pub type WatchCallback = fn(Threshold, Crossing, &Readings);
+
+/// Handle on a registered watch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchId(usize);
+
+// Samples the temperature and the core voltage every period
+//    There is no task to do it in the background: `poll` has to be called regularly from the
+//    main loop, and samples when the period elapsed. Callbacks are called from `poll`, so they
+//    can use the mailbox, e.g. to lower the ARM clock.
+pub struct Monitor(SpinLock<MonitorInner>);
+
+#[derive(Clone, Copy)]
+struct Watch {
+    threshold: Threshold,
+    callback: WatchCallback,
+    crossed: bool,
+}
+
+struct MonitorInner {
+    period_us: u64,
+    next_sample_us: u64,
+    latest: Option<Readings>,
+    watches: [Option<Watch>; MAX_WATCHES],
+}
+
+impl Monitor {
+    const fn init() -> Monitor {
+        Monitor(SpinLock::new(MonitorInner {
+            period_us: DEFAULT_PERIOD_US,
+            next_sample_us: 0,
+            latest: None,
+            watches: [None; MAX_WATCHES],
+        }))
+    }
+
+    pub fn set_period(&self, period_us: u64) {
+        self.0.lock(|monitor| monitor.period_us = period_us);
+    }
+
+    // Last sample taken, None before the first one
+    pub fn readings(&self) -> Option<Readings> {
+        self.0.lock(|monitor| monitor.latest)
+    }
+
+    // The callback is called once when the threshold is crossed, and once when it is cleared
+    pub fn watch(&self, threshold: Threshold, callback: WatchCallback) -> Result<WatchId, Errcode> {
+        self.0.lock(|monitor| {
+            let idx = monitor
+                .watches
+                .iter()
+                .position(|w| w.is_none())
+                .ok_or(Errcode::MonitorTooManyWatches)?;
+            monitor.watches[idx] = Some(Watch {
+                threshold,
+                callback,
+                crossed: false,
+            });
+            Ok(WatchId(idx))
+        })
+    }
+
+    pub fn unwatch(&self, id: WatchId) -> Result<(), Errcode> {
+        self.0.lock(|monitor| match monitor.watches.get_mut(id.0) {
+            Some(watch @ Some(_)) => {
+                *watch = None;
+                Ok(())
+            }
+            _ => Err(Errcode::MonitorInvalidWatch),
+        })
+    }
+
+    // Sample if the period elapsed since the last sample
+    pub fn poll(&self) -> Result<(), Errcode> {
+        let now = TIMER.now();
+        let due = self.0.lock(|monitor| {
+            if now < monitor.next_sample_us {
+                return false;
+            }
+            // Before sampling, so that sensors failing are only asked again at the next period
+            monitor.next_sample_us = now + monitor.period_us;
+            true
+        });
+        if due {
+            self.sample()?;
+        }
+        Ok(())
+    }
+
+    // Sample now, whatever the period
+    pub fn sample(&self) -> Result<Readings, Errcode> {
+        let max_temperature = match self.readings() {
+            Some(readings) => readings.max_temperature,
+            None => query_temperature(RpiMailboxTag::GetMaxTemperature)?,
+        };
+        let temperature = match THERMAL.temperature() {
+            Some(temperature) => temperature,
+            None => query_temperature(RpiMailboxTag::GetTemperature)?,
+        };
+        let readings = Readings {
+            temperature,
+            max_temperature,
+            core_voltage: query_core_voltage()?,
+            timestamp_us: TIMER.now(),
+        };
+
+        let mut triggered = [None; MAX_WATCHES];
+        self.0.lock(|monitor| {
+            monitor.latest = Some(readings);
+            monitor.next_sample_us = readings.timestamp_us + monitor.period_us;
+            for (watch, triggered) in monitor.watches.iter_mut().zip(triggered.iter_mut()) {
+                if let Some(watch) = watch {
+                    *triggered = watch.update(&readings);
+                }
+            }
+        });
+        // Outside of the lock, the callbacks may use the monitor
+        for (callback, threshold, crossing) in triggered.into_iter().flatten() {
+            callback(threshold, crossing, &readings);
+        }
+        Ok(readings)
+    }
+}
+
+impl Watch {
+    // What happened to the threshold with these readings, if anything
+    fn update(&mut self, readings: &Readings) -> Option<(WatchCallback, Threshold, Crossing)> {
+        let (crossed, cleared) = match self.threshold {
+            Threshold::TemperatureAbove(limit) => (
+                readings.temperature >= limit,
+                readings.temperature < limit - TEMPERATURE_HYSTERESIS,
+            ),
+            Threshold::VoltageBelow(limit) => (
+                readings.core_voltage <= limit,
+                readings.core_voltage > limit + VOLTAGE_HYSTERESIS,
+            ),
+        };
+        let crossing = if !self.crossed && crossed {
+            Crossing::Crossed
+        } else if self.crossed && cleared {
+            Crossing::Cleared
+        } else {
+            return None;
+        };
+        self.crossed = crossing == Crossing::Crossed;
+        Some((self.callback, self.threshold, crossing))
+    }
+}
+
+impl fmt::Display for Readings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "SoC {}.{:03} C (throttles at {}.{:03} C), core {}.{:06} V",
+            self.temperature / 1000,
+            (self.temperature % 1000).abs(),
+            self.max_temperature / 1000,
+            (self.max_temperature % 1000).abs(),
+            self.core_voltage / 1_000_000,
+            self.core_voltage % 1_000_000
+        )
+    }
+}
+
+// `sensors` on the serial console, takes a fresh sample
+pub(crate) fn register_command() -> Result<(), Errcode> {
+    console::register_command(Command {
+        name: "sensors",
+        help: "show the SoC temperature and core voltage",
+        run: |_| match MONITOR.sample() {
+            Ok(readings) => println!("{readings}"),
+            Err(err) => println!("Unable to read the sensors: {err}"),
+        },
+    })
+}
+
+// In millidegrees Celsius
+fn query_temperature(tag: RpiMailboxTag) -> Result<i32, Errcode> {
+    match MAILBOX.query(tag)? {
+        RpiMailboxReply::TemperatureState(temperature) => Ok(temperature as i32),
+        _ => unreachable!("Reply to a tag we did not send"),
+    }
+}
+
+// In microvolts
+fn query_core_voltage() -> Result<u32, Errcode> {
+    match MAILBOX.query(RpiMailboxTag::GetVoltage(TagVoltageId::Core))? {
+        RpiMailboxReply::VoltageState { value, .. } => Ok(value),
+        _ => unreachable!("Reply to a tag we did not send"),
+    }
+}
@@ -0,0 +1,113 @@
+use crate::drivers::generic_timer::delay_us;
+use crate::errors::Errcode;
+use crate::mailboxes::{RpiMailboxReply, RpiMailboxTag, MAILBOX};
+use crate::println;
+use crate::sync::SpinLock;
+
+const NB_DEVICES: usize = 9;
+
+// How many users each power domain has, indexed by `Device`
+static USERS: SpinLock<[u32; NB_DEVICES]> = SpinLock::new([0; NB_DEVICES]);
+
+/// Power domains the firmware lets us switch.
+pub use crate::mailboxes::TagDeviceId as Device;
+
+const ALL_DEVICES: [Device; NB_DEVICES] = [
+    Device::SdCard,
+    Device::Uart0,
+    Device::Uart1,
+    Device::UsbHcd,
+    Device::I2c0,
+    Device::I2c1,
+    Device::I2c2,
+    Device::Spi,
+    Device::Ccp2tx,
+];
+
+// Drivers take a reference on the domain of their device while they use it
+//    The first reference powers it on, and returns once it is stable.
+pub fn acquire(device: Device) -> Result<(), Errcode> {
+    USERS.lock(|users| {
+        if users[device as usize] == 0 {
+            power_on(device)?;
+        }
+        users[device as usize] += 1;
+        Ok(())
+    })
+}
+
+// The last reference powers the domain off
+pub fn release(device: Device) -> Result<(), Errcode> {
+    USERS.lock(|users| match users[device as usize] {
+        0 => Err(Errcode::PowerNotAcquired(device as u32)),
+        1 => {
+            set_power_state(device, false)?;
+            users[device as usize] = 0;
+            Ok(())
+        }
+        _ => {
+            users[device as usize] -= 1;
+            Ok(())
+        }
+    })
+}
+
+// Whether the firmware reports the domain as powered, whoever turned it on
+pub fn is_on(device: Device) -> Result<bool, Errcode> {
+    match MAILBOX.query(RpiMailboxTag::GetPowerState(device))? {
+        RpiMailboxReply::PowerState {
+            not_exists: true, ..
+        } => Err(Errcode::PowerDeviceNotFound(device as u32)),
+        RpiMailboxReply::PowerState { on, .. } => Ok(on),
+        _ => unreachable!("Reply to a tag we did not send"),
+    }
+}
+
+// Power off every domain nobody holds a reference on
+//    The firmware may have left some on for us, e.g. the SD card it booted from. Only saves some
+//    power: a domain that cannot be switched off is reported, and the others are still tried.
+pub fn power_off_unused() {
+    USERS.lock(|users| {
+        for device in ALL_DEVICES {
+            if users[device as usize] > 0 {
+                continue;
+            }
+            let res = match is_on(device) {
+                Ok(true) => set_power_state(device, false),
+                Ok(false) => Ok(()),
+                Err(err) => Err(err),
+            };
+            match res {
+                // Not every board has all the domains
+                Ok(()) | Err(Errcode::PowerDeviceNotFound(_)) => {}
+                Err(err) => println!("Unable to power off {device:?}: {err}"),
+            }
+        }
+    })
+}
+
+fn power_on(device: Device) -> Result<(), Errcode> {
+    let wait_us = match MAILBOX.query(RpiMailboxTag::GetTiming(device))? {
+        RpiMailboxReply::PowerTiming { wait_micro_sec, .. } => wait_micro_sec,
+        _ => unreachable!("Reply to a tag we did not send"),
+    };
+    set_power_state(device, true)?;
+    delay_us(u64::from(wait_us));
+    Ok(())
+}
+
+fn set_power_state(device: Device, on: bool) -> Result<(), Errcode> {
+    let reply = MAILBOX.query(RpiMailboxTag::SetPowerState {
+        device_id: device,
+        on,
+        wait: false,
+    })?;
+    match reply {
+        RpiMailboxReply::PowerState {
+            not_exists: true, ..
+        } => Err(Errcode::PowerDeviceNotFound(device as u32)),
+        RpiMailboxReply::PowerState { on: state, .. } if state == on => Ok(()),
+        RpiMailboxReply::PowerState { .. } => Err(Errcode::PowerStateNotChanged(device as u32)),
+        _ => unreachable!("Reply to a tag we did not send"),
+    }
+}
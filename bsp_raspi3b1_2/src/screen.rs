@@ -1,10 +1,66 @@
-#[derive(Default)]
+use core::ops::Deref;
+
+use crate::errors::Errcode;
+use crate::font::{default_font, Font};
+use crate::mailboxes::{PixelOrder, PropertyRequest, RpiMailboxReply, RpiMailboxTag, MAILBOX};
+use crate::memory::bus_to_phys;
+use crate::mmu;
+
+// The firmware wants the framebuffer aligned on this, in bytes
+const FRAMEBUFFER_ALIGNMENT: u32 = 16;
+
+#[derive(Debug, Clone, Copy)]
 pub struct TextStyle {
+    foreground: Color,
+    background: Color,
     bold: bool,
     italic: bool,
     underlined: bool,
 }
 
+impl TextStyle {
+    pub const fn new(foreground: Color, background: Color) -> TextStyle {
+        TextStyle {
+            foreground,
+            background,
+            bold: false,
+            italic: false,
+            underlined: false,
+        }
+    }
+
+    pub const fn bold(mut self) -> TextStyle {
+        self.bold = true;
+        self
+    }
+
+    pub const fn italic(mut self) -> TextStyle {
+        self.italic = true;
+        self
+    }
+
+    pub const fn underlined(mut self) -> TextStyle {
+        self.underlined = true;
+        self
+    }
+
+    pub const fn foreground(&self) -> Color {
+        self.foreground
+    }
+
+    pub const fn background(&self) -> Color {
+        self.background
+    }
+}
+
+// White on black
+impl Default for TextStyle {
+    fn default() -> Self {
+        TextStyle::new(Color::WHITE, Color::BLACK)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Color {
     r: u8,
     g: u8,
@@ -12,42 +68,447 @@ pub struct Color {
     a: u8,
 }
 
+impl Color {
+    pub const BLACK: Color = Color::rgb(0, 0, 0);
+    pub const WHITE: Color = Color::rgb(255, 255, 255);
+    pub const RED: Color = Color::rgb(255, 0, 0);
+    pub const GREEN: Color = Color::rgb(0, 255, 0);
+    pub const BLUE: Color = Color::rgb(0, 0, 255);
+
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Color {
+        Color { r, g, b, a: 255 }
+    }
+
+    pub const fn rgba(r: u8, g: u8, b: u8, a: u8) -> Color {
+        Color { r, g, b, a }
+    }
+}
+
+// Framebuffer allocated by the VideoCore
+//    Only 16 bpp (RGB565) and 32 bpp are supported. The framebuffer holds one or two canvases
+//    stacked vertically, the screen shows the one at the current virtual offset.
+//    Drawing through the screen itself goes to the canvas on display.
 pub struct Screen {
+    buffers: [Canvas; 2],
+    nb_buffers: usize,
+    front: usize,
+    vsync_available: bool,
+}
+
+// One screen-sized image in the framebuffer
+//    Drawing outside of it is clipped. Text is laid out on a grid of font-sized cells, addressed
+//    by line and column.
+#[derive(Clone, Copy)]
+pub struct Canvas {
+    font: &'static Font,
     width: u32,
     height: u32,
+    depth: u32,
+    pitch: u32,
+    pixel_order: PixelOrder,
+    base: usize,
 }
 
 impl Screen {
-    pub fn init() -> Screen {
-        todo!();
+    // Ask the firmware for a framebuffer of this size and depth (bits per pixel)
+    pub fn init(width: u32, height: u32, depth: u32) -> Result<Screen, Errcode> {
+        Screen::allocate(width, height, depth, 1)
+    }
+
+    // Same, with a second canvas to draw the next frame in while the first one is on display
+    //    Falls back to a single canvas if the firmware refuses the larger virtual size.
+    pub fn init_double_buffered(width: u32, height: u32, depth: u32) -> Result<Screen, Errcode> {
+        Screen::allocate(width, height, depth, 2)
+    }
+
+    fn allocate(width: u32, height: u32, depth: u32, nb_buffers: u32) -> Result<Screen, Errcode> {
+        if depth != 16 && depth != 32 {
+            return Err(Errcode::ScreenUnsupportedDepth(depth));
+        }
+
+        let mut request = PropertyRequest::new();
+        request
+            .add(RpiMailboxTag::SetPhysicalSize { width, height })?
+            .add(RpiMailboxTag::SetVirtualSize {
+                width,
+                height: height * nb_buffers,
+            })?
+            .add(RpiMailboxTag::SetVirtualOffset { x: 0, y: 0 })?
+            .add(RpiMailboxTag::SetDepth(depth))?
+            .add(RpiMailboxTag::SetPixelOrder(PixelOrder::Rgb))?
+            .add(RpiMailboxTag::AllocateBuffer(FRAMEBUFFER_ALIGNMENT))?
+            .add(RpiMailboxTag::GetPitch)?;
+
+        let mut canvas = Canvas {
+            font: default_font(),
+            width: 0,
+            height: 0,
+            depth: 0,
+            pitch: 0,
+            pixel_order: PixelOrder::Rgb,
+            base: 0,
+        };
+        let mut virtual_height = 0;
+        let mut size = 0;
+        let mut physical_size_set = false;
+        for reply in MAILBOX.send(&request)? {
+            match reply {
+                // The physical size comes first, then the virtual one
+                RpiMailboxReply::ScreenSize { width, height } if !physical_size_set => {
+                    canvas.width = width;
+                    canvas.height = height;
+                    physical_size_set = true;
+                }
+                RpiMailboxReply::ScreenSize { height, .. } => virtual_height = height,
+                RpiMailboxReply::VirtualOffset { .. } => {}
+                RpiMailboxReply::ScreenDepth(depth) => canvas.depth = depth,
+                RpiMailboxReply::PixelOrder(order) => canvas.pixel_order = order,
+                RpiMailboxReply::FramebufferAllocation {
+                    base_addr,
+                    buff_size,
+                } => {
+                    canvas.base = bus_to_phys(base_addr);
+                    size = buff_size as usize;
+                }
+                RpiMailboxReply::ScreenPitch(pitch) => canvas.pitch = pitch,
+                _ => unreachable!("Reply to a tag we did not send"),
+            }
+        }
+
+        // The firmware may fall back to another depth than the one requested
+        if canvas.depth != 16 && canvas.depth != 32 {
+            return Err(Errcode::ScreenUnsupportedDepth(canvas.depth));
+        }
+        if canvas.base == 0 || size == 0 || canvas.pitch == 0 || canvas.height == 0 {
+            return Err(Errcode::ScreenAllocationFailed);
+        }
+
+        let canvas_size = canvas.height as usize * canvas.pitch as usize;
+        let nb_buffers = (virtual_height / canvas.height)
+            .min(nb_buffers)
+            .min((size / canvas_size) as u32)
+            .max(1) as usize;
+        let back = Canvas {
+            base: canvas.base + canvas_size,
+            ..canvas
+        };
+
+        mmu::set_non_cacheable(canvas.base, size);
+        Ok(Screen {
+            buffers: [canvas, back],
+            nb_buffers,
+            front: 0,
+            vsync_available: true,
+        })
+    }
+
+    pub fn is_double_buffered(&self) -> bool {
+        self.nb_buffers == 2
+    }
+
+    pub fn set_font(&mut self, font: &'static Font) {
+        for canvas in self.buffers.iter_mut() {
+            canvas.font = font;
+        }
+    }
+
+    // Canvas to draw the next frame in, not on display until `present`
+    //    Without double buffering, this is the canvas on display.
+    pub fn back_buffer(&self) -> &Canvas {
+        &self.buffers[(self.front + 1) % self.nb_buffers]
     }
 
-    // pub fn write_text(&self, line: u32, msg: String, style: TextStyle) {
-    //     todo!();
-    // }
+    // Put the back buffer on display, the previous front buffer becomes the back buffer
+    //    The firmware switches at the next vertical blanking. When it can tell us when that is,
+    //    we wait for it, so the new back buffer is no longer scanned out once this returns.
+    pub fn present(&mut self) -> Result<(), Errcode> {
+        if self.nb_buffers < 2 {
+            return Ok(());
+        }
+        let back = (self.front + 1) % self.nb_buffers;
+        MAILBOX.query(RpiMailboxTag::SetVirtualOffset {
+            x: 0,
+            y: back as u32 * self.buffers[back].height,
+        })?;
+        self.front = back;
+
+        // Given up after the first failure, the firmware either always answers it or never does
+        if self.vsync_available && MAILBOX.query(RpiMailboxTag::WaitForVsync).is_err() {
+            self.vsync_available = false;
+        }
+        Ok(())
+    }
+}
+
+impl Deref for Screen {
+    type Target = Canvas;
+
+    fn deref(&self) -> &Canvas {
+        &self.buffers[self.front]
+    }
+}
+
+impl Canvas {
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    // Number of text lines and columns that fit on the screen with the current font
+    pub fn lines(&self) -> u32 {
+        self.height / self.font.height() as u32
+    }
+
+    pub fn columns(&self) -> u32 {
+        self.width / self.font.width() as u32
+    }
+
+    pub fn write_text(&self, line: u32, msg: &str, style: TextStyle) {
+        self.write_text_at(line, 0, msg, style);
+    }
+
+    // Text going past the end of the line is clipped, there is no wrapping
+    pub fn write_text_at(&self, line: u32, column: u32, msg: &str, style: TextStyle) {
+        if line >= self.lines() {
+            return;
+        }
+        for (column, c) in (column..self.columns()).zip(msg.chars()) {
+            self.draw_char(line, column, c, style);
+        }
+    }
+
+    // Render one glyph in its cell, background included
+    pub fn draw_char(&self, line: u32, column: u32, c: char, style: TextStyle) {
+        let (font_width, font_height) = (self.font.width() as i64, self.font.height() as i64);
+        let x0 = i64::from(column) * font_width;
+        let y0 = i64::from(line) * font_height;
+        let foreground = self.pixel_value(style.foreground);
+        let background = self.pixel_value(style.background);
+
+        for y in 0..font_height {
+            // Shear: the top of the glyph leans right, the bottom left
+            let shift = if style.italic {
+                (font_height - 1 - y) / 6 - 1
+            } else {
+                0
+            };
+            let underline = style.underlined && y == font_height - 2;
+            for x in 0..font_width {
+                let glyph_x = (x - shift) as isize;
+                let mut set = self.font.pixel(c, glyph_x, y as isize);
+                // Double strike, one pixel to the right
+                if style.bold {
+                    set |= self.font.pixel(c, glyph_x - 1, y as isize);
+                }
+                let value = if set || underline {
+                    foreground
+                } else {
+                    background
+                };
+                self.put_pixel_clipped(x0 + x, y0 + y, value);
+            }
+        }
+    }
+
+    // Move the whole text up by `lines`, the freed lines at the bottom are cleared
+    pub fn scroll_up(&self, lines: u32, background: Color) {
+        let rows = ((lines * self.font.height() as u32).min(self.height)) as usize;
+        if rows == 0 {
+            return;
+        }
+        let pitch = self.pitch as usize;
+        let kept = self.height as usize - rows;
+        unsafe {
+            core::ptr::copy(
+                (self.base + rows * pitch) as *const u8,
+                self.base as *mut u8,
+                kept * pitch,
+            );
+        }
+        self.draw_rect(
+            (0, kept as u32),
+            (self.width - 1, self.height - 1),
+            background,
+            true,
+        );
+    }
+
+    // Blank text line
+    pub fn clear_line(&self, line: u32, background: Color) {
+        let font_height = self.font.height() as u32;
+        let top = line * font_height;
+        if top < self.height {
+            self.draw_rect(
+                (0, top),
+                (self.width - 1, top + font_height - 1),
+                background,
+                true,
+            );
+        }
+    }
 
     pub fn draw_pixel(&self, x: u32, y: u32, color: Color) {
-        todo!();
+        if x < self.width && y < self.height {
+            self.put_pixel(x, y, self.pixel_value(color));
+        }
     }
 
+    // Bresenham, works in every octant
     pub fn draw_line(&self, start: (u32, u32), end: (u32, u32), color: Color) {
-        todo!();
+        let value = self.pixel_value(color);
+        let (mut x, mut y) = (i64::from(start.0), i64::from(start.1));
+        let (x1, y1) = (i64::from(end.0), i64::from(end.1));
+        let dx = (x1 - x).abs();
+        let dy = -(y1 - y).abs();
+        let step_x = if x < x1 { 1 } else { -1 };
+        let step_y = if y < y1 { 1 } else { -1 };
+        let mut err = dx + dy;
+
+        loop {
+            self.put_pixel_clipped(x, y, value);
+            if x == x1 && y == y1 {
+                break;
+            }
+            let err2 = 2 * err;
+            if err2 >= dy {
+                err += dy;
+                x += step_x;
+            }
+            if err2 <= dx {
+                err += dx;
+                y += step_y;
+            }
+        }
     }
 
+    // `start` and `end` are two opposite corners, both included
     pub fn draw_rect(&self, start: (u32, u32), end: (u32, u32), color: Color, fill: bool) {
-        todo!();
+        let (left, right) = (start.0.min(end.0), start.0.max(end.0));
+        let (top, bottom) = (start.1.min(end.1), start.1.max(end.1));
+
+        if fill {
+            let value = self.pixel_value(color);
+            for y in top..=bottom {
+                self.draw_span(i64::from(left), i64::from(right), i64::from(y), value);
+            }
+        } else {
+            self.draw_line((left, top), (right, top), color);
+            self.draw_line((left, bottom), (right, bottom), color);
+            self.draw_line((left, top), (left, bottom), color);
+            self.draw_line((right, top), (right, bottom), color);
+        }
     }
 
+    // Circle of radius `size`, midpoint algorithm
     pub fn draw_ellipse(&self, center: (u32, u32), size: u32, color: Color, fill: bool) {
-        todo!();
+        let value = self.pixel_value(color);
+        let (cx, cy) = (i64::from(center.0), i64::from(center.1));
+        let mut x = i64::from(size);
+        let mut y = 0;
+        let mut err = 1 - x;
+
+        while x >= y {
+            if fill {
+                self.draw_span(cx - x, cx + x, cy + y, value);
+                self.draw_span(cx - x, cx + x, cy - y, value);
+                self.draw_span(cx - y, cx + y, cy + x, value);
+                self.draw_span(cx - y, cx + y, cy - x, value);
+            } else {
+                for (px, py) in [
+                    (x, y),
+                    (y, x),
+                    (-y, x),
+                    (-x, y),
+                    (-x, -y),
+                    (-y, -x),
+                    (y, -x),
+                    (x, -y),
+                ] {
+                    self.put_pixel_clipped(cx + px, cy + py, value);
+                }
+            }
+
+            y += 1;
+            if err < 0 {
+                err += 2 * y + 1;
+            } else {
+                x -= 1;
+                err += 2 * (y - x) + 1;
+            }
+        }
     }
 
-    // pub fn draw_polygon(&self, points: Vec<u32>, color: Color) {
-    //     assert!(points.len() < 2, "Not enough points to draw the polygon");
-    //     todo!();
-    // }
+    // Outline through all the points, the last one linked back to the first
+    pub fn draw_polygon(&self, points: &[(u32, u32)], color: Color) {
+        assert!(points.len() >= 2, "Not enough points to draw the polygon");
+        for (idx, start) in points.iter().enumerate() {
+            let end = points[(idx + 1) % points.len()];
+            self.draw_line(*start, end, color);
+        }
+    }
 
     pub fn fill_screen(&self, color: Color) {
-        todo!();
+        let value = self.pixel_value(color);
+        for y in 0..self.height {
+            self.draw_span(0, i64::from(self.width) - 1, i64::from(y), value);
+        }
+    }
+
+    // Color as stored in the framebuffer, for the depth and pixel order in use
+    fn pixel_value(&self, color: Color) -> u32 {
+        let (first, second, third) = match self.pixel_order {
+            PixelOrder::Rgb => (color.r, color.g, color.b),
+            PixelOrder::Bgr => (color.b, color.g, color.r),
+        };
+        match self.depth {
+            16 => {
+                (u32::from(third >> 3) << 11)
+                    | (u32::from(second >> 2) << 5)
+                    | u32::from(first >> 3)
+            }
+            _ => {
+                u32::from(first)
+                    | (u32::from(second) << 8)
+                    | (u32::from(third) << 16)
+                    | (u32::from(color.a) << 24)
+            }
+        }
+    }
+
+    // Horizontal line from `x0` to `x1` included
+    fn draw_span(&self, x0: i64, x1: i64, y: i64, value: u32) {
+        if y < 0 || y >= i64::from(self.height) {
+            return;
+        }
+        let x0 = x0.max(0);
+        let x1 = x1.min(i64::from(self.width) - 1);
+        for x in x0..=x1 {
+            self.put_pixel(x as u32, y as u32, value);
+        }
+    }
+
+    fn put_pixel_clipped(&self, x: i64, y: i64, value: u32) {
+        if x >= 0 && y >= 0 && x < i64::from(self.width) && y < i64::from(self.height) {
+            self.put_pixel(x as u32, y as u32, value);
+        }
+    }
+
+    // Coordinates must be on the screen
+    fn put_pixel(&self, x: u32, y: u32, value: u32) {
+        let offset = (y * self.pitch) as usize + (x * self.depth / 8) as usize;
+        let addr = self.base + offset;
+        unsafe {
+            match self.depth {
+                16 => core::ptr::write_volatile(addr as *mut u16, value as u16),
+                _ => core::ptr::write_volatile(addr as *mut u32, value),
+            }
+        }
     }
 }
@@ -1,27 +1,359 @@
 use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
 
-pub struct NullLock<T>
+use crate::cpu;
+use crate::mmu;
+
+static LOCKS_BYPASSED: AtomicBool = AtomicBool::new(false);
+
+// From now on, every lock hands out its data without waiting
+//    Only for the panic path: a lock held by the code that failed will never be released, and
+//    printing the panic is the last thing the kernel does.
+pub(crate) fn bypass_locks() {
+    LOCKS_BYPASSED.store(true, Ordering::Relaxed);
+}
+
+// Whether the locks have to be taken without the atomics
+//    The BCM2837 has no global exclusive monitor, exclusive load / store pairs are unreliable
+//    until the MMU and the caches are on. Before that, a single core runs: masking the IRQs while
+//    the data is used is enough.
+fn bypassed() -> bool {
+    LOCKS_BYPASSED.load(Ordering::Relaxed) || !mmu::is_enabled()
+}
+
+// Use the data of a lock taken without the atomics
+fn bypass<T: ?Sized, R>(data: &UnsafeCell<T>, f: impl FnOnce(&mut T) -> R) -> R {
+    let daif = cpu::irq_save();
+    let res = f(unsafe { &mut *data.get() });
+    cpu::irq_restore(daif);
+    res
+}
+
+// Ticket spinlock
+//    Each locker takes a ticket and waits for its number to be served, so the lock is handed out
+//    in FIFO order and nobody starves. The atomics compile down to exclusive load / store pairs,
+//    see `bypassed` for when they cannot be used.
+//
+//    An IRQ-safe lock masks IRQs and FIQs on the core for as long as it is held, it has to be
+//    used for any data also accessed from an IRQ handler, otherwise the handler could spin
+//    forever on a lock held by the code it interrupted.
+pub struct SpinLock<T>
 where
     T: ?Sized,
 {
+    next_ticket: AtomicU32,
+    now_serving: AtomicU32,
+    irq_safe: bool,
     data: UnsafeCell<T>,
 }
 
-unsafe impl<T> Send for NullLock<T> where T: ?Sized + Send {}
-unsafe impl<T> Sync for NullLock<T> where T: ?Sized + Send {}
+unsafe impl<T> Send for SpinLock<T> where T: ?Sized + Send {}
+unsafe impl<T> Sync for SpinLock<T> where T: ?Sized + Send {}
 
-impl<T> NullLock<T> {
+impl<T> SpinLock<T> {
     pub const fn new(data: T) -> Self {
         Self {
+            next_ticket: AtomicU32::new(0),
+            now_serving: AtomicU32::new(0),
+            irq_safe: false,
             data: UnsafeCell::new(data),
         }
     }
 
-    pub fn lock<'a, R>(&'a self, f: impl FnOnce(&'a mut T) -> R) -> R {
-        // In a real lock, there would be code encapsulating this line that ensures that this
-        // mutable reference will ever only be given out once at a time.
-        let data = unsafe { &mut *self.data.get() };
+    pub const fn new_irqsafe(data: T) -> Self {
+        Self {
+            next_ticket: AtomicU32::new(0),
+            now_serving: AtomicU32::new(0),
+            irq_safe: true,
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T> SpinLock<T>
+where
+    T: ?Sized,
+{
+    pub fn lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        if bypassed() {
+            return bypass(&self.data, f);
+        }
+        let daif = if self.irq_safe {
+            Some(cpu::irq_save())
+        } else {
+            None
+        };
+
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        while self.now_serving.load(Ordering::Acquire) != ticket {
+            core::hint::spin_loop();
+        }
+
+        // Our ticket is served, nobody else can get here until we release it
+        let res = f(unsafe { &mut *self.data.get() });
+
+        self.now_serving.fetch_add(1, Ordering::Release);
+        if let Some(daif) = daif {
+            cpu::irq_restore(daif);
+        }
+        res
+    }
+}
+
+// Readers-writer spinlock
+//    Any number of readers, or a single writer. A writer waiting for the lock prevents new readers
+//    from entering, so a steady flow of readers cannot starve it.
+//    Same IRQ-safety rules as the `SpinLock`.
+pub struct RwLock<T>
+where
+    T: ?Sized,
+{
+    state: AtomicU32,
+    irq_safe: bool,
+    data: UnsafeCell<T>,
+}
+
+const RWLOCK_WRITER: u32 = 1 << 31;
+const RWLOCK_WRITER_WAITING: u32 = 1 << 30;
+const RWLOCK_READERS_MASK: u32 = RWLOCK_WRITER_WAITING - 1;
+
+unsafe impl<T> Send for RwLock<T> where T: ?Sized + Send {}
+unsafe impl<T> Sync for RwLock<T> where T: ?Sized + Send + Sync {}
+
+impl<T> RwLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            state: AtomicU32::new(0),
+            irq_safe: false,
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub const fn new_irqsafe(data: T) -> Self {
+        Self {
+            state: AtomicU32::new(0),
+            irq_safe: true,
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T> RwLock<T>
+where
+    T: ?Sized,
+{
+    pub fn read<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        if bypassed() {
+            return bypass(&self.data, |data| f(data));
+        }
+        let daif = if self.irq_safe {
+            Some(cpu::irq_save())
+        } else {
+            None
+        };
+
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if (state & (RWLOCK_WRITER | RWLOCK_WRITER_WAITING)) == 0 {
+                assert!(
+                    (state & RWLOCK_READERS_MASK) != RWLOCK_READERS_MASK,
+                    "Too many readers on RwLock"
+                );
+                if self
+                    .state
+                    .compare_exchange_weak(state, state + 1, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    break;
+                }
+            }
+            core::hint::spin_loop();
+        }
+
+        let res = f(unsafe { &*self.data.get() });
+
+        self.state.fetch_sub(1, Ordering::Release);
+        if let Some(daif) = daif {
+            cpu::irq_restore(daif);
+        }
+        res
+    }
+
+    pub fn write<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        if bypassed() {
+            return bypass(&self.data, f);
+        }
+        let daif = if self.irq_safe {
+            Some(cpu::irq_save())
+        } else {
+            None
+        };
+
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if (state & !RWLOCK_WRITER_WAITING) == 0 {
+                if self
+                    .state
+                    .compare_exchange_weak(
+                        state,
+                        RWLOCK_WRITER,
+                        Ordering::Acquire,
+                        Ordering::Relaxed,
+                    )
+                    .is_ok()
+                {
+                    break;
+                }
+            } else if (state & RWLOCK_WRITER_WAITING) == 0 {
+                self.state
+                    .fetch_or(RWLOCK_WRITER_WAITING, Ordering::Relaxed);
+            }
+            core::hint::spin_loop();
+        }
+
+        let res = f(unsafe { &mut *self.data.get() });
+
+        // Also clears the waiting flag, other waiting writers will raise it again
+        self.state.store(0, Ordering::Release);
+        if let Some(daif) = daif {
+            cpu::irq_restore(daif);
+        }
+        res
+    }
+}
+
+// Value initialized once at runtime, then only read
+//    Usable in a static, `Once<()>` simply guards a piece of code to be executed only once.
+//    Do not use from IRQ handlers a value that may be under initialization by the code they
+//    interrupted, they would spin forever.
+pub struct Once<T = ()> {
+    state: AtomicU8,
+    data: UnsafeCell<MaybeUninit<T>>,
+}
+
+const ONCE_INCOMPLETE: u8 = 0;
+const ONCE_RUNNING: u8 = 1;
+const ONCE_COMPLETE: u8 = 2;
+
+unsafe impl<T> Send for Once<T> where T: Send {}
+unsafe impl<T> Sync for Once<T> where T: Send + Sync {}
+
+impl<T> Once<T> {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(ONCE_INCOMPLETE),
+            data: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    pub fn call_once(&self, f: impl FnOnce() -> T) -> &T {
+        match self.try_call_once(|| Ok::<T, core::convert::Infallible>(f())) {
+            Ok(data) => data,
+            Err(e) => match e {},
+        }
+    }
+
+    // If `f` fails, the value stays uninitialized and a later call can try again
+    pub fn try_call_once<E>(&self, f: impl FnOnce() -> Result<T, E>) -> Result<&T, E> {
+        loop {
+            match self.state.compare_exchange_weak(
+                ONCE_INCOMPLETE,
+                ONCE_RUNNING,
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    return match f() {
+                        Ok(data) => {
+                            unsafe { (*self.data.get()).write(data) };
+                            self.state.store(ONCE_COMPLETE, Ordering::Release);
+                            Ok(unsafe { (*self.data.get()).assume_init_ref() })
+                        }
+                        Err(e) => {
+                            self.state.store(ONCE_INCOMPLETE, Ordering::Release);
+                            Err(e)
+                        }
+                    };
+                }
+                Err(ONCE_COMPLETE) => return Ok(unsafe { (*self.data.get()).assume_init_ref() }),
+                // Being initialized by someone else, or spurious failure
+                Err(_) => core::hint::spin_loop(),
+            }
+        }
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        if self.is_completed() {
+            Some(unsafe { (*self.data.get()).assume_init_ref() })
+        } else {
+            None
+        }
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Ordering::Acquire) == ONCE_COMPLETE
+    }
+}
+
+impl<T> Default for Once<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for Once<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == ONCE_COMPLETE {
+            unsafe { self.data.get_mut().assume_init_drop() };
+        }
+    }
+}
+
+// Value computed on first access
+pub struct Lazy<T, F = fn() -> T> {
+    cell: Once<T>,
+    init: UnsafeCell<Option<F>>,
+}
+
+unsafe impl<T, F> Sync for Lazy<T, F>
+where
+    T: Send + Sync,
+    F: Send,
+{
+}
+
+impl<T, F> Lazy<T, F>
+where
+    F: FnOnce() -> T,
+{
+    pub const fn new(init: F) -> Self {
+        Self {
+            cell: Once::new(),
+            init: UnsafeCell::new(Some(init)),
+        }
+    }
+
+    pub fn force(this: &Self) -> &T {
+        this.cell.call_once(|| {
+            // Only reached once, by the caller which won the initialization
+            let init = unsafe { (*this.init.get()).take() };
+            match init {
+                Some(f) => f(),
+                None => unreachable!("Lazy value initialized twice"),
+            }
+        })
+    }
+}
+
+impl<T, F> Deref for Lazy<T, F>
+where
+    F: FnOnce() -> T,
+{
+    type Target = T;
 
-        f(data)
+    fn deref(&self) -> &T {
+        Lazy::force(self)
     }
 }
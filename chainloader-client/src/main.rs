@@ -3,14 +3,13 @@
 
 use core::panic::PanicInfo;
 
-use bsp_raspi3b1_2::drivers::gpio::PinMode;
+use bsp_raspi3b1_2::chainloader_binary_load;
+use bsp_raspi3b1_2::drivers::pins::Pins;
 use bsp_raspi3b1_2::errors::handle_panic;
-use bsp_raspi3b1_2::{chainloader_binary_load, dbg, spin_for_cycles};
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     let uart = &bsp_raspi3b1_2::drivers::UART;
-    uart.configure(14, 15);
     uart.write("KO");
     handle_panic(info);
 }
@@ -18,10 +17,23 @@ fn panic(info: &PanicInfo) -> ! {
 #[no_mangle]
 pub fn _start_rust() -> ! {
     let uart = &bsp_raspi3b1_2::drivers::UART;
-    uart.configure(14, 15);
-    chainloader_binary_load(uart);
+    let pins = Pins::take().expect("Unable to take the pins");
+    let txd = pins
+        .p14
+        .into_uart0_txd()
+        .expect("Unable to take the UART TX pin");
+    let rxd = pins
+        .p15
+        .into_uart0_rxd()
+        .expect("Unable to take the UART RX pin");
+    uart.configure_pins(txd, rxd)
+        .expect("Unable to configure the UART");
+    match chainloader_binary_load(uart) {
+        Ok(never) => match never {},
+        Err(err) => panic!("Unable to chainload: {err}"),
+    }
     // loop {
     //     uart.write("3");
-    //     spin_for_cycles(1_200_000);
+    //     delay_ms(500);
     // }
 }
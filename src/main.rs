@@ -3,7 +3,18 @@
 
 use core::panic::PanicInfo;
 
-use bsp_raspi3b1_2::{drivers::gpio::PinMode, errors::handle_panic, println, spin_for_cycles};
+use bsp_raspi3b1_2::{
+    board::board_info,
+    console::{attach_screen, poll_input},
+    drivers::pins::{mode::Output, Pin, Pins},
+    errors::handle_panic,
+    init::init_bsp,
+    monitor::MONITOR,
+    println,
+    screen::{Screen, TextStyle},
+};
+
+const LED_PIN: usize = 21;
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
@@ -14,15 +25,41 @@ fn panic(info: &PanicInfo) -> ! {
 
 #[no_mangle]
 pub fn _start_rust() -> ! {
-    let gpio = &bsp_raspi3b1_2::drivers::GPIO;
-    gpio.configure(&[(21, PinMode::Output)]);
+    init_bsp().expect("Unable to initialize the BSP");
+    // The serial console is enough to run without a display
+    match Screen::init(1024, 768, 32) {
+        Ok(screen) => {
+            attach_screen(screen, TextStyle::default()).expect("Unable to attach the screen");
+        }
+        Err(err) => println!("No screen: {err}"),
+    }
+    println!("{}", board_info().expect("Unable to get the board info"));
+    let pins = Pins::take().expect("Unable to take the pins");
+    let mut led = pins.p21.into_output().expect("Unable to set up the LED");
     loop {
-        println!("LED ON");
-        gpio.set_pin(21);
-        spin_for_cycles(2_000_000);
+        poll_input();
+        if let Err(err) = MONITOR.poll() {
+            println!("Unable to read the sensors: {err}");
+        }
+        blink(&mut led);
+    }
+}
+
+fn blink(led: &mut Pin<LED_PIN, Output>) {
+    println!("LED ON");
+    led.set_high();
+    wait_polling_input(1_000_000);
+
+    println!("LED OFF");
+    led.set_low();
+    wait_polling_input(250_000);
+}
 
-        println!("LED OFF");
-        gpio.clear_pin(21);
-        spin_for_cycles(500_000);
+// Keep reading the serial console while waiting, the UART only buffers 16 bytes
+fn wait_polling_input(time_us: u64) {
+    let timer = &bsp_raspi3b1_2::drivers::TIMER;
+    let deadline = timer.now() + time_us;
+    while timer.now() < deadline {
+        poll_input();
     }
 }